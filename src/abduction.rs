@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    engine::Engine,
+    fact::{Fact, Rule},
+};
+
+//Какие сырые факты (их не даёт ни одно правило) добавить к начальным, чтобы вывести цель.
+//Наборы минимальны по включению, с smallest - ещё и по размеру. Не больше limit наборов.
+pub fn missing_starting_facts(
+    engine: &Engine,
+    target: &Fact,
+    limit: usize,
+    smallest: bool,
+) -> Vec<Vec<Fact>> {
+    let mut producers: HashMap<Fact, Vec<Rule>> = HashMap::new();
+    for r in engine.ground_rules() {
+        for out in &r.outs {
+            let v = producers.entry(out.fact.clone()).or_default();
+            if !v.contains(&r) {
+                v.push(r.clone());
+            }
+        }
+    }
+    let mut a = Abduction {
+        producers,
+        starting: &engine.starting_facts,
+        memo: HashMap::new(),
+    };
+    let mut candidates = a.sets(target, &mut vec![]).0;
+    candidates.sort_by_cached_key(|x| (x.len(), sorted(x)));
+    //Отрицания при поиске не учитываются, поэтому каждый набор проверяется полным выводом.
+    let mut res: Vec<Vec<Fact>> = vec![];
+    for c in candidates {
+        if res.len() >= limit || (smallest && res.first().is_some_and(|x| x.len() < c.len())) {
+            break;
+        }
+        let mut e = engine.clone();
+        e.starting_facts.extend(c.iter().cloned());
+        if e.model().contains(target) {
+            let mut c: Vec<Fact> = c.into_iter().collect();
+            c.sort_by_cached_key(|x| x.to_string());
+            res.push(c);
+        }
+    }
+    res
+}
+
+fn sorted(set: &HashSet<Fact>) -> Vec<String> {
+    let mut v: Vec<String> = set.iter().map(|x| x.to_string()).collect();
+    v.sort();
+    v
+}
+
+//Оставляет только минимальные по включению наборы.
+fn minimize(mut sets: Vec<HashSet<Fact>>) -> Vec<HashSet<Fact>> {
+    sets.sort_by_key(|x| x.len());
+    let mut res: Vec<HashSet<Fact>> = vec![];
+    for s in sets {
+        if !res.iter().any(|x| x.is_subset(&s)) {
+            res.push(s);
+        }
+    }
+    res
+}
+
+struct Abduction<'a> {
+    producers: HashMap<Fact, Vec<Rule>>,
+    starting: &'a HashSet<Fact>,
+    memo: HashMap<Fact, Vec<HashSet<Fact>>>,
+}
+
+impl Abduction<'_> {
+    //Минимальные наборы сырых фактов для fact. path - факты, которые сейчас выводятся.
+    //Второе значение - обрезан ли поиск циклом: такой результат зависит от пути и не запоминается.
+    fn sets(&mut self, fact: &Fact, path: &mut Vec<Fact>) -> (Vec<HashSet<Fact>>, bool) {
+        if self.starting.contains(fact) {
+            return (vec![HashSet::new()], false);
+        }
+        if let Some(x) = self.memo.get(fact) {
+            return (x.clone(), false);
+        }
+        if path.contains(fact) {
+            return (vec![], true);
+        }
+        let Some(rules) = self.producers.get(fact).cloned() else {
+            let sets = if fact.is_ground() {
+                vec![HashSet::from([fact.clone()])]
+            } else {
+                vec![]
+            };
+            return (sets, false);
+        };
+        path.push(fact.clone());
+        let mut cut = false;
+        let mut res = vec![];
+        for r in rules {
+            let mut slots: Vec<Vec<&Fact>> = r.reqs.iter().map(|x| vec![&x.fact]).collect();
+            slots.extend(r.groups.iter().map(|g| g.iter().map(|x| &x.fact).collect()));
+            slots.extend(r.catalysts.iter().map(|x| vec![x]));
+            let mut combos = vec![HashSet::new()];
+            for slot in slots {
+                let mut options = vec![];
+                for x in slot {
+                    let (sets, c) = self.sets(x, path);
+                    cut |= c;
+                    options.extend(sets);
+                }
+                let mut next = vec![];
+                for a in &combos {
+                    for b in &options {
+                        next.push(a.union(b).cloned().collect());
+                    }
+                }
+                combos = minimize(next);
+                if combos.is_empty() {
+                    break;
+                }
+            }
+            res.extend(combos);
+        }
+        path.pop();
+        let res = minimize(res);
+        if !cut {
+            self.memo.insert(fact.clone(), res.clone());
+        }
+        (res, cut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn missing(src: &str, limit: usize, smallest: bool) -> Vec<Vec<String>> {
+        let e = Engine::from_string(src).unwrap();
+        let target = e
+            .all_possible_facts
+            .iter()
+            .find(|x| x.to_string() == "pickaxe");
+        missing_starting_facts(&e, target.unwrap(), limit, smallest)
+            .iter()
+            .map(|x| x.iter().map(|x| x.to_string()).collect())
+            .collect()
+    }
+
+    const PICKAXE: &str = "{plank, stick} -> pickaxe\n{wood} -> plank\n{plank} -> stick\n\
+                           {bamboo} -> stick\n{iron, stick} -> pickaxe\n{wood, iron} -> pickaxe\n\
+                           {gold, coal} -> pickaxe\n{sand, !wet} -> pickaxe\n{bamboo} -> wet\n\
+                           start: bamboo\n";
+
+    #[test]
+    fn only_minimal_sets() {
+        assert_eq!(
+            missing(PICKAXE, 10, false),
+            [vec!["iron"], vec!["wood"], vec!["coal", "gold"]]
+        );
+        assert_eq!(missing(PICKAXE, 10, true), [["iron"], ["wood"]]);
+        assert_eq!(missing(PICKAXE, 1, false), [["iron"]]);
+        let given = PICKAXE.replace("start: bamboo", "start: bamboo, iron");
+        assert_eq!(missing(&given, 10, false), [Vec::<String>::new()]);
+    }
+}
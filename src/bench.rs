@@ -0,0 +1,96 @@
+use std::{collections::HashSet, fmt::Write, time::Instant};
+
+use crate::{
+    direct_reasoning::{DirectReasoning, StepResult},
+    engine::Engine,
+    fact::Rule,
+};
+
+//Сравнение прямого вывода с индексом и прежнего перебора всех правил на каждом шаге.
+//Запуск: cargo run --release -- --bench 20000
+pub fn forward_chaining(n: usize) -> String {
+    let e = chain(n);
+    let start = Instant::now();
+    let scanned = scan(&e);
+    let scan_time = start.elapsed();
+    let start = Instant::now();
+    let mut d = DirectReasoning::with_targets(&e, e.goals.clone());
+    let mut indexed = 0;
+    while let StepResult::Applied(_) | StepResult::FoundAfter(..) = d.step() {
+        indexed += 1;
+    }
+    let indexed_time = start.elapsed();
+    assert_eq!(scanned, indexed, "both runs fire the same rules");
+    let mut s = String::new();
+    _ = writeln!(s, "{} rules, {} firings", e.rules.len(), indexed);
+    _ = writeln!(s, "full scan: {:?}", scan_time);
+    _ = writeln!(s, "indexed:   {:?}", indexed_time);
+    _ = writeln!(
+        s,
+        "speedup:   {:.1}x",
+        scan_time.as_secs_f64() / indexed_time.as_secs_f64().max(1e-9)
+    );
+    s
+}
+
+//Цепочка, объявленная с конца: каждое следующее правило готово только после предыдущего,
+//так что перебор проходит почти всю базу на каждом шаге.
+fn chain(n: usize) -> Engine {
+    let mut s = String::new();
+    for i in (0..n).rev() {
+        _ = writeln!(s, "{{f{}, f{}}} -> f{}", i, i / 2, i + 1);
+    }
+    _ = writeln!(s, "start: f0");
+    _ = writeln!(s, "goal: f{}", n);
+    Engine::from_string(&s).expect("generated base is valid")
+}
+
+//Прежний прямой вывод без индекса, для правил без переменных.
+fn scan(e: &Engine) -> usize {
+    let strata = e.strata().unwrap_or_default();
+    let mut facts = e.starting_facts.clone();
+    let mut unused: HashSet<Rule> = e.rules.iter().cloned().collect();
+    let mut fired = 0;
+    loop {
+        let next = e
+            .rules
+            .iter()
+            .filter(|x| unused.contains(*x) && x.match_requirement(&facts))
+            .min_by_key(|x| strata.get(*x).copied());
+        let Some(r) = next.cloned() else {
+            return fired;
+        };
+        facts.extend(r.outs.iter().map(|x| x.fact.clone()));
+        unused.remove(&r);
+        fired += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indexed(e: &Engine) -> usize {
+        let mut d = DirectReasoning::with_targets(e, e.goals.clone());
+        let mut fired = 0;
+        while let StepResult::Applied(_) | StepResult::FoundAfter(..) = d.step() {
+            fired += 1;
+        }
+        fired
+    }
+
+    #[test]
+    fn indexed_run_agrees_with_full_scan() {
+        for n in [1, 2, 10, 257] {
+            let e = chain(n);
+            assert_eq!(indexed(&e), scan(&e), "chain of {}", n);
+            assert_eq!(scan(&e), n);
+        }
+        let e = Engine::from_string(
+            "{a, b} -> c\n{a} -> b\n{c, !d} -> e\n{b} -> d\n{x} -> y\nx\nstart: a\ngoal: e\n",
+        )
+        .unwrap();
+        assert_eq!(indexed(&e), scan(&e));
+        assert!(forward_chaining(100).starts_with("100 rules, 100 firings"));
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+
+use petgraph::{algo::tarjan_scc, graphmap::DiGraphMap};
+
+use crate::{
+    engine::Engine,
+    fact::{Fact, Rule},
+    ruletree::{RuleTree, Source},
+};
+
+//Все различные деревья вывода цели, не больше limit штук.
+//Деревья для очередного правила цели строятся только когда предыдущие закончились.
+//Факт не выводится через самого себя, так что циклы дают конечное число деревьев.
+//Способы получить факт вне циклов не зависят от пути к нему и считаются один раз.
+pub struct Derivations {
+    target: Fact,
+    starting_facts: HashSet<Fact>,
+    producers: HashMap<Fact, Vec<Rule>>,
+    next_rule: usize,
+    ready: Vec<RuleTree>,
+    left: usize,
+    //С dedup выводы из одного и того же набора правил выдаются один раз.
+    seen: Option<HashSet<BTreeSet<String>>>,
+    //Факты, лежащие на циклах: их способы зависят от того, что сейчас выводится.
+    cyclic: HashSet<Fact>,
+    //Для остальных: с каким limit искали и что нашли.
+    memo: RefCell<HashMap<Fact, (usize, Vec<Source>)>>,
+}
+
+impl Derivations {
+    pub fn new(engine: &Engine, target: Fact, limit: usize, dedup: bool) -> Self {
+        //Отрицания проверяются по всем выводимым фактам, как в стратифицированном выводе.
+        let model = engine.model();
+        let mut producers: HashMap<Fact, Vec<Rule>> = HashMap::new();
+        for r in engine.ground_rules() {
+            if r.negs.iter().any(|x| model.contains(x)) {
+                continue;
+            }
+            for out in &r.outs {
+                let v = producers.entry(out.fact.clone()).or_default();
+                if !v.contains(&r) {
+                    v.push(r.clone());
+                }
+            }
+        }
+        let facts: Vec<&Fact> = producers.keys().collect();
+        let index: HashMap<&Fact, usize> = facts.iter().enumerate().map(|(i, &f)| (f, i)).collect();
+        let mut g = DiGraphMap::<usize, ()>::new();
+        for (f, rules) in &producers {
+            g.add_node(index[f]);
+            if engine.starting_facts.contains(f) {
+                continue;
+            }
+            for r in rules {
+                let reqs = r.reqs.iter().chain(r.groups.iter().flatten());
+                for x in reqs.map(|x| &x.fact).chain(r.catalysts.iter()) {
+                    //Факты без правил - листья, на циклах они не лежат.
+                    if let Some(&to) = index.get(x) {
+                        g.add_edge(index[f], to, ());
+                    }
+                }
+            }
+        }
+        let cyclic = tarjan_scc(&g)
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || g.contains_edge(scc[0], scc[0]))
+            .flatten()
+            .map(|i| facts[i].clone())
+            .collect();
+        Derivations {
+            //Начальный факт выводить не нужно.
+            left: if engine.starting_facts.contains(&target) {
+                0
+            } else {
+                limit
+            },
+            target,
+            starting_facts: engine.starting_facts.clone(),
+            producers,
+            next_rule: 0,
+            ready: vec![],
+            seen: dedup.then(HashSet::new),
+            cyclic,
+            memo: RefCell::new(HashMap::new()),
+        }
+    }
+    //Способы получить fact, не больше limit. path - факты, которые сейчас выводятся.
+    fn sources(&self, fact: &Fact, path: &mut Vec<Fact>, limit: usize) -> Vec<Source> {
+        if self.starting_facts.contains(fact) {
+            return vec![Source::BasicFact(fact.clone())];
+        }
+        if path.contains(fact) {
+            return vec![];
+        }
+        //Найденное с меньшим limit годится, если нашлось всё, что было.
+        if let Some((searched, found)) = self.memo.borrow().get(fact) {
+            if limit <= *searched || found.len() < *searched {
+                return found.iter().take(limit).cloned().collect();
+            }
+        }
+        let mut res = vec![];
+        for r in self.producers.get(fact).into_iter().flatten() {
+            if res.len() >= limit {
+                break;
+            }
+            let trees = self.trees(fact, r, path, limit - res.len());
+            res.extend(trees.into_iter().map(|x| Source::Rule(Arc::new(x))));
+        }
+        if !self.cyclic.contains(fact) {
+            self.memo
+                .borrow_mut()
+                .insert(fact.clone(), (limit, res.clone()));
+        }
+        res
+    }
+    //Деревья вывода fact правилом rule: все сочетания способов получить требования.
+    fn trees(&self, fact: &Fact, rule: &Rule, path: &mut Vec<Fact>, limit: usize) -> Vec<RuleTree> {
+        path.push(fact.clone());
+        let mut slots: Vec<Vec<Source>> = vec![];
+        for x in &rule.reqs {
+            slots.push(self.sources(&x.fact, path, limit));
+        }
+        for g in &rule.groups {
+            let mut options = vec![];
+            for x in g {
+                if options.len() >= limit {
+                    break;
+                }
+                options.extend(self.sources(&x.fact, path, limit - options.len()));
+            }
+            slots.push(options);
+        }
+        for x in &rule.catalysts {
+            slots.push(self.sources(x, path, limit));
+        }
+        path.pop();
+        let mut combos: Vec<Vec<Source>> = vec![vec![]];
+        for slot in &slots {
+            combos = combos
+                .iter()
+                .flat_map(|c| {
+                    slot.iter().map(move |s| {
+                        let mut c = c.clone();
+                        c.push(s.clone());
+                        c
+                    })
+                })
+                .take(limit)
+                .collect();
+        }
+        combos
+            .into_iter()
+            .map(|sources| RuleTree {
+                fact: fact.clone(),
+                rule: Some(rule.clone()),
+                sources,
+            })
+            .collect()
+    }
+}
+
+impl Iterator for Derivations {
+    type Item = RuleTree;
+    fn next(&mut self) -> Option<RuleTree> {
+        while self.left > 0 {
+            let Some(tree) = self.ready.pop() else {
+                let rule = self
+                    .producers
+                    .get(&self.target)?
+                    .get(self.next_rule)?
+                    .clone();
+                self.next_rule += 1;
+                let mut trees = self.trees(&self.target.clone(), &rule, &mut vec![], self.left);
+                trees.reverse();
+                self.ready = trees;
+                continue;
+            };
+            if let Some(seen) = &mut self.seen {
+                let mut rules = BTreeSet::new();
+                rule_set(&tree, &mut rules, &mut HashSet::new());
+                if !seen.insert(rules) {
+                    continue;
+                }
+            }
+            self.left -= 1;
+            return Some(tree);
+        }
+        None
+    }
+}
+
+//Правила дерева без повторов. Общее поддерево обходится один раз.
+fn rule_set(tree: &RuleTree, rules: &mut BTreeSet<String>, visited: &mut HashSet<*const RuleTree>) {
+    rules.extend(tree.rule.iter().map(|x| x.to_string()));
+    for s in &tree.sources {
+        if let Source::Rule(t) = s {
+            if visited.insert(Arc::as_ptr(t)) {
+                rule_set(t, rules, visited);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derivations(src: &str, target: &str, limit: usize, dedup: bool) -> Vec<Vec<String>> {
+        let e = Engine::from_string(src).unwrap();
+        let f = e
+            .all_possible_facts
+            .iter()
+            .find(|x| x.to_string() == target);
+        Derivations::new(&e, f.unwrap().clone(), limit, dedup)
+            .map(|t| t.rules().iter().map(|x| x.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn every_combination_of_recipes() {
+        let src = "{log} -> plank\n{bamboo} -> plank\n{plank} -> stick\n\
+                   {plank, stick} -> sign\n{iron} -> sign\nstart: log, bamboo, iron\n";
+        let all = derivations(src, "sign", 10, false);
+        assert_eq!(all.len(), 5);
+        assert_eq!(
+            all[0],
+            [
+                "{log} -> plank",
+                "{log} -> plank",
+                "{plank} -> stick",
+                "{plank, stick} -> sign"
+            ]
+        );
+        assert_eq!(all[4], ["{iron} -> sign"]);
+        assert_eq!(derivations(src, "sign", 3, false), all[..3]);
+        assert!(derivations(src, "log", 10, false).is_empty());
+    }
+
+    #[test]
+    fn dedup_keeps_one_tree_per_rule_set() {
+        let src = "{x | y} -> t\nstart: x, y\n";
+        assert_eq!(derivations(src, "t", 10, false).len(), 2);
+        assert_eq!(derivations(src, "t", 10, true).len(), 1);
+    }
+
+    #[test]
+    fn cycles_end() {
+        let src = "{a} -> b\n{b} -> a\n{c} -> a\nstart: c\n";
+        assert_eq!(derivations(src, "b", 10, false), [["{c} -> a", "{a} -> b"]]);
+    }
+
+    //Узлы дерева, общее поддерево считается один раз.
+    fn size(tree: &RuleTree, visited: &mut HashSet<*const RuleTree>) -> usize {
+        let mut n = 1;
+        for s in &tree.sources {
+            if let Source::Rule(t) = s {
+                if visited.insert(Arc::as_ptr(t)) {
+                    n += size(t, visited);
+                }
+            }
+        }
+        n
+    }
+
+    #[test]
+    fn shared_subgoals_grow_linearly() {
+        //{f(i), f(i-1)} -> f(i+1): без запоминания дерево растёт как числа Фибоначчи.
+        let fibonacci = |n: usize| {
+            let mut src = String::new();
+            for i in 1..n {
+                src += &format!("{{f{}, f{}}} -> f{}\n", i, i - 1, i + 1);
+            }
+            src += "start: f0, f1\n";
+            let e = Engine::from_string(&src).unwrap();
+            let f = e
+                .all_possible_facts
+                .iter()
+                .find(|x| x.to_string() == format!("f{}", n));
+            let all: Vec<RuleTree> = Derivations::new(&e, f.unwrap().clone(), 10, true).collect();
+            assert_eq!(all.len(), 1);
+            size(&all[0], &mut HashSet::new())
+        };
+        assert_eq!(fibonacci(50), 49);
+        assert_eq!(fibonacci(200), 199);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    collections::{HashMap, HashSet},
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     sync::{Arc, RwLock},
 };
 
@@ -9,6 +10,8 @@ use petgraph::{stable_graph::StableGraph, Directed};
 use crate::{
     engine::Engine,
     fact::{Fact, Rule},
+    ruletree::{RuleTree, Source},
+    unify,
 };
 #[derive(Debug, Clone)]
 pub struct DirectReasoning {
@@ -17,9 +20,138 @@ pub struct DirectReasoning {
     starting_facts: HashSet<Fact>,
     pub all_rules: Vec<Rule>,
     current_facts: HashSet<Fact>,
-    target_fact: Fact,
-    used_rules: Vec<Rule>,
+    //Вывод заканчивается, когда получены все цели.
+    targets: Vec<Fact>,
+    pub used_rules: Vec<Rule>,
     pub unused_rules: HashSet<Rule>,
+    strata: HashMap<Rule, usize>,
+    //Применённые частные случаи правил с переменными -> исходное правило.
+    instances: HashMap<Rule, Rule>,
+    strategy: ConflictStrategy,
+    random: u64,
+    //Номер шага, на котором появился факт (начальные - 0), для стратегии Recency.
+    added: HashMap<Fact, usize>,
+    index: RuleIndex,
+}
+//Индекс правил без переменных по фактам требований, как в линейном алгоритме для хорновских
+//формул: у каждого правила счётчик невыполненных требований, новый факт уменьшает счётчики
+//только у правил, которые его ждут. Готовые правила лежат по стратам в порядке объявления.
+#[derive(Debug, Clone, Default)]
+struct RuleIndex {
+    //Факт -> (номер правила, номер требования). Группа - одно требование из нескольких фактов.
+    watchers: HashMap<Fact, Vec<(usize, usize)>>,
+    //Факт под отрицанием -> номера правил.
+    blockers: HashMap<Fact, Vec<usize>>,
+    //Номера одного и того же правила: одинаковые правила срабатывают один раз.
+    positions: HashMap<Rule, Vec<usize>>,
+    satisfied: Vec<Vec<bool>>,
+    missing: Vec<usize>,
+    blocked: Vec<usize>,
+    //Правило без переменных, ещё не сработавшее и не отсечённое.
+    active: Vec<bool>,
+    stratum: Vec<usize>,
+    ready: BTreeMap<usize, BTreeSet<usize>>,
+    //В базе нет правил с переменными: всё, что может сработать, есть в ready.
+    all_ground: bool,
+}
+impl RuleIndex {
+    fn new(rules: &[Rule], strata: &HashMap<Rule, usize>, facts: &HashSet<Fact>) -> Self {
+        let mut index = RuleIndex {
+            all_ground: rules.iter().all(|x| x.is_ground()),
+            ..Default::default()
+        };
+        for (i, r) in rules.iter().enumerate() {
+            let ground = r.is_ground();
+            let mut slots: Vec<Vec<&Fact>> = r.reqs.iter().map(|x| vec![&x.fact]).collect();
+            slots.extend(r.groups.iter().map(|g| g.iter().map(|x| &x.fact).collect()));
+            slots.extend(r.catalysts.iter().map(|x| vec![x]));
+            if ground {
+                for (j, slot) in slots.iter().enumerate() {
+                    for f in slot {
+                        index.watchers.entry((*f).clone()).or_default().push((i, j));
+                    }
+                }
+                for f in &r.negs {
+                    index.blockers.entry(f.clone()).or_default().push(i);
+                }
+                index.positions.entry(r.clone()).or_default().push(i);
+            }
+            index.missing.push(slots.len());
+            index.satisfied.push(vec![false; slots.len()]);
+            index.blocked.push(0);
+            index.active.push(ground);
+            index.stratum.push(strata.get(r).copied().unwrap_or(0));
+        }
+        for i in 0..rules.len() {
+            index.update(i);
+        }
+        for f in facts {
+            index.add_fact(f);
+        }
+        index
+    }
+    //Вызывается один раз для каждого нового факта.
+    fn add_fact(&mut self, fact: &Fact) {
+        for (i, j) in self.watchers.get(fact).cloned().unwrap_or_default() {
+            if !self.satisfied[i][j] {
+                self.satisfied[i][j] = true;
+                self.missing[i] -= 1;
+                self.update(i);
+            }
+        }
+        for i in self.blockers.get(fact).cloned().unwrap_or_default() {
+            self.blocked[i] += 1;
+            self.update(i);
+        }
+    }
+    //Правило больше не срабатывает: уже применено или отсечено.
+    fn deactivate(&mut self, rule: &Rule) {
+        for i in self.positions.get(rule).cloned().unwrap_or_default() {
+            self.active[i] = false;
+            self.update(i);
+        }
+    }
+    fn update(&mut self, i: usize) {
+        let stratum = self.stratum[i];
+        if self.active[i] && self.missing[i] == 0 && self.blocked[i] == 0 {
+            self.ready.entry(stratum).or_default().insert(i);
+        } else if let Some(set) = self.ready.get_mut(&stratum) {
+            set.remove(&i);
+            if set.is_empty() {
+                self.ready.remove(&stratum);
+            }
+        }
+    }
+    //Первое объявленное готовое правило нижней страты.
+    fn first(&self) -> Option<usize> {
+        self.ready.values().next()?.first().copied()
+    }
+    fn ready(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ready.values().flatten().copied()
+    }
+}
+//Какое из подходящих правил срабатывает. Правила нижних страт всё равно идут первыми.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    #[default]
+    DeclarationOrder,
+    FewestRequirements,
+    MostRequirements,
+    Recency,  //Правило на самых новых фактах.
+    Priority, //@priority=N, большее - раньше; без атрибута 0.
+    Random(u64),
+}
+impl ConflictStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConflictStrategy::DeclarationOrder => "Declaration order",
+            ConflictStrategy::FewestRequirements => "Fewest requirements",
+            ConflictStrategy::MostRequirements => "Most requirements",
+            ConflictStrategy::Recency => "Recency",
+            ConflictStrategy::Priority => "Priority",
+            ConflictStrategy::Random(_) => "Random",
+        }
+    }
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum StepResult {
@@ -30,16 +162,137 @@ pub enum StepResult {
 }
 impl DirectReasoning {
     pub fn new(rules: &Engine, target_fact: Fact) -> Self {
+        Self::with_targets(rules, vec![target_fact])
+    }
+    pub fn with_targets(rules: &Engine, targets: Vec<Fact>) -> Self {
+        Self::with_strategy(rules, targets, ConflictStrategy::default())
+    }
+    pub fn with_strategy(rules: &Engine, targets: Vec<Fact>, strategy: ConflictStrategy) -> Self {
+        //База без циклов через отрицание проверяется при загрузке.
+        let strata = rules.strata().unwrap_or_default();
         DirectReasoning {
             //rules: rules.clone(),
             starting_facts: rules.starting_facts.clone(),
             all_facts: rules.all_possible_facts.clone(),
             all_rules: rules.rules.clone(),
             current_facts: rules.starting_facts.iter().cloned().collect(),
-            target_fact,
+            targets,
             used_rules: vec![],
             unused_rules: rules.rules.iter().cloned().collect(),
+            strata: strata.clone(),
+            instances: HashMap::new(),
+            strategy,
+            random: match strategy {
+                ConflictStrategy::Random(seed) => seed,
+                _ => 0,
+            },
+            added: rules
+                .starting_facts
+                .iter()
+                .map(|x| (x.clone(), 0))
+                .collect(),
+            index: RuleIndex::new(&rules.rules, &strata, &rules.starting_facts),
+        }
+    }
+    //Правила, которые могут сработать сейчас, вместе с исходными правилами, в порядке объявления.
+    //Правила без переменных берутся из индекса, правила с переменными подставляются
+    //по мере появления подходящих фактов.
+    fn applicable(&self) -> Vec<(Rule, Rule)> {
+        let mut res: Vec<(usize, Rule, Rule)> = self
+            .index
+            .ready()
+            .map(|i| (i, self.all_rules[i].clone(), self.all_rules[i].clone()))
+            .collect();
+        for (n, r) in self.all_rules.iter().enumerate() {
+            if r.is_ground() || !self.unused_rules.contains(r) {
+                continue;
+            }
+            let mut found: Vec<_> = unify::matches(r, &self.current_facts)
+                .iter()
+                .map(|b| unify::instantiate(r, b))
+                .filter(|i| {
+                    !self.instances.contains_key(i) && i.match_requirement(&self.current_facts)
+                })
+                .collect();
+            //Подстановки приходят в порядке HashSet, так что сортируем.
+            found.sort_by_key(|x| x.to_string());
+            found.dedup();
+            res.extend(found.into_iter().map(|i| (n, i, r.clone())));
         }
+        //Сортировка устойчивая: подстановки одного правила остаются по порядку.
+        res.sort_by_key(|x| x.0);
+        res.into_iter().map(|(_, r, s)| (r, s)).collect()
+    }
+    //Шаги появления фактов, на которых сработает правило, от новых к старым.
+    fn recency(&self, r: &Rule) -> Vec<usize> {
+        let time = |f: &Fact| self.added.get(f).copied();
+        let mut res: Vec<_> = r
+            .reqs
+            .iter()
+            .filter_map(|x| time(&x.fact))
+            .chain(r.catalysts.iter().filter_map(time))
+            .chain(
+                r.groups
+                    .iter()
+                    .filter_map(|g| g.iter().filter_map(|x| time(&x.fact)).max()),
+            )
+            .collect();
+        res.sort_by(|a, b| b.cmp(a));
+        res
+    }
+    fn next_random(&mut self) -> u64 {
+        //splitmix64
+        self.random = self.random.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    fn select(&mut self, applicable: Vec<(Rule, Rule)>) -> Option<(Rule, Rule)> {
+        let stratum = |x: &(Rule, Rule)| self.strata.get(&x.1).copied();
+        let lowest = applicable.iter().map(stratum).min()?;
+        let mut v: Vec<_> = applicable
+            .into_iter()
+            .filter(|x| stratum(x) == lowest)
+            .collect();
+        let size = |r: &Rule| r.reqs.len() + r.groups.len() + r.catalysts.len() + r.negs.len();
+        let priority = |r: &Rule| r.attr("priority").and_then(|x| x.parse::<i64>().ok());
+        //min_by_key при равенстве берёт первое, то есть объявленное раньше.
+        let i = match self.strategy {
+            ConflictStrategy::DeclarationOrder => 0,
+            ConflictStrategy::FewestRequirements => (0..v.len()).min_by_key(|&i| size(&v[i].0))?,
+            ConflictStrategy::MostRequirements => {
+                (0..v.len()).min_by_key(|&i| Reverse(size(&v[i].0)))?
+            }
+            ConflictStrategy::Recency => {
+                (0..v.len()).min_by_key(|&i| Reverse(self.recency(&v[i].0)))?
+            }
+            ConflictStrategy::Priority => {
+                (0..v.len()).min_by_key(|&i| Reverse(priority(&v[i].0).unwrap_or(0)))?
+            }
+            ConflictStrategy::Random(_) => (self.next_random() % v.len() as u64) as usize,
+        };
+        Some(v.swap_remove(i))
+    }
+    //Оставляет только правила, которые могут участвовать в выводе цели.
+    pub fn prune(&mut self, rules: &Engine) {
+        let mut relevant = HashSet::new();
+        for t in &self.targets {
+            relevant.extend(rules.relevant_rules(t));
+        }
+        for r in self.all_rules.iter().filter(|x| !relevant.contains(*x)) {
+            self.index.deactivate(r);
+        }
+        self.unused_rules.retain(|x| relevant.contains(x));
+    }
+    //Сколько ещё правил сработает до конца вывода.
+    pub fn remaining_firings(&self) -> usize {
+        let mut d = self.clone();
+        let mut n = 0;
+        while let StepResult::Applied(_) | StepResult::FoundAfter(..) = d.step() {
+            n += 1;
+        }
+        n
     }
     pub fn update_hashmap(&self, color: &NodeColoring) {
         let mut c = color.facts.write().unwrap();
@@ -54,22 +307,23 @@ impl DirectReasoning {
         for i in &self.starting_facts {
             c.insert(i.clone(), FactState::Starting);
         }
-        if self.current_facts.contains(&self.target_fact) {
-            c.insert(self.target_fact.clone(), FactState::TargetVisited);
-        } else if !self
-            .unused_rules
-            .iter()
-            .any(|x| x.match_requirement(&self.current_facts))
-        {
-            c.insert(self.target_fact.clone(), FactState::TargetNotPossible);
-        } else {
-            c.insert(self.target_fact.clone(), FactState::Target);
+        let finished = self.applicable().is_empty();
+        for t in &self.targets {
+            let state = if self.current_facts.contains(t) {
+                FactState::TargetVisited
+            } else if finished {
+                FactState::TargetNotPossible
+            } else {
+                FactState::Target
+            };
+            c.insert(t.clone(), state);
         }
-        for i in &self.unused_rules {
+        for i in &self.all_rules {
             r.insert(i.clone(), RuleState::None);
         }
         for i in &self.used_rules {
-            r.insert(i.clone(), RuleState::Visited);
+            let rule = self.instances.get(i).unwrap_or(i);
+            r.insert(rule.clone(), RuleState::Visited);
         }
     }
     pub fn try_find(&mut self) -> StepResult {
@@ -82,27 +336,109 @@ impl DirectReasoning {
             }
         }
     }
+    //Дерево вывода первой цели.
+    pub fn proof(&self) -> Option<RuleTree> {
+        self.proof_of(self.targets.first()?)
+    }
+    //Дерево вывода target по применённым правилам. Каждый факт берётся из первого правила,
+    //которое его получило, так что поддеревья опираются только на более ранние шаги.
+    pub fn proof_of(&self, target: &Fact) -> Option<RuleTree> {
+        let mut known: HashMap<Fact, usize> =
+            self.starting_facts.iter().map(|x| (x.clone(), 0)).collect();
+        let mut producers: HashMap<Fact, (usize, Rule)> = HashMap::new();
+        for (i, r) in self.used_rules.iter().enumerate() {
+            for out in &r.outs {
+                if !known.contains_key(&out.fact) {
+                    known.insert(out.fact.clone(), i + 1);
+                    producers.insert(out.fact.clone(), (i, r.clone()));
+                }
+            }
+        }
+        fn source(
+            f: &Fact,
+            known: &HashMap<Fact, usize>,
+            producers: &HashMap<Fact, (usize, Rule)>,
+        ) -> Source {
+            let Some((i, r)) = producers.get(f) else {
+                return Source::BasicFact(f.clone());
+            };
+            //Из группы - вариант, который уже был к моменту применения правила.
+            let options = r.groups.iter().filter_map(|g| {
+                g.iter()
+                    .find(|x| known.get(&x.fact).is_some_and(|&t| t <= *i))
+                    .map(|x| &x.fact)
+            });
+            let sources = r
+                .reqs
+                .iter()
+                .map(|x| &x.fact)
+                .chain(options)
+                .chain(r.catalysts.iter())
+                .map(|x| source(x, known, producers))
+                .collect();
+            Source::Rule(Arc::new(RuleTree {
+                fact: f.clone(),
+                rule: Some(r.clone()),
+                sources,
+            }))
+        }
+        match source(target, &known, &producers) {
+            Source::Rule(t) => Some((*t).clone()),
+            Source::BasicFact(_) => None,
+        }
+    }
+    fn all_found(&self) -> bool {
+        self.targets.iter().all(|x| self.current_facts.contains(x))
+    }
+    //Следующее правило и его исходное правило.
+    //Сначала правила нижних страт: пока они могут сработать, отсутствие факта не окончательно.
+    fn choose(&mut self) -> Option<(Rule, Rule)> {
+        //Без правил с переменными первое по объявлению правило берётся прямо из индекса.
+        if self.strategy == ConflictStrategy::DeclarationOrder && self.index.all_ground {
+            self.index
+                .first()
+                .map(|i| (self.all_rules[i].clone(), self.all_rules[i].clone()))
+        } else {
+            let applicable = self.applicable();
+            self.select(applicable)
+        }
+    }
+    fn apply(&mut self, r: &Rule, rule: Rule) {
+        let time = self.used_rules.len() + 1;
+        for out in &r.outs {
+            self.added.entry(out.fact.clone()).or_insert(time);
+            if self.current_facts.insert(out.fact.clone()) {
+                self.index.add_fact(&out.fact);
+            }
+        }
+        if rule.is_ground() {
+            self.unused_rules.remove(r);
+            self.index.deactivate(r);
+        } else {
+            self.instances.insert(r.clone(), rule);
+        }
+        self.used_rules.push(r.clone());
+    }
     pub fn step(&mut self) -> StepResult {
-        if self.current_facts.contains(&self.target_fact) {
+        if self.all_found() {
             return StepResult::Found;
         }
-        if let Some(r) = self
-            .unused_rules
-            .iter()
-            .find(|x| x.match_requirement(&self.current_facts))
-        {
-            let r = r.clone();
-            let f = r.out.clone();
-            self.current_facts.insert(f.clone());
-            self.unused_rules.remove(&r);
-            self.used_rules.push(r.clone());
-            if f == self.target_fact {
-                return StepResult::FoundAfter(r, f);
-            } else {
-                return StepResult::Applied(r);
-            }
+        let Some((r, rule)) = self.choose() else {
+            return StepResult::NotProved;
+        };
+        self.apply(&r, rule);
+        //FoundAfter - когда правило дало последнюю недостающую цель.
+        match self.targets.iter().find(|x| r.produces(x)) {
+            Some(t) if self.all_found() => StepResult::FoundAfter(r, t.clone()),
+            _ => StepResult::Applied(r),
+        }
+    }
+    //Все выводимые факты: вывод до конца, не останавливаясь на целях.
+    pub fn model(mut self) -> HashSet<Fact> {
+        while let Some((r, rule)) = self.choose() {
+            self.apply(&r, rule);
         }
-        StepResult::NotProved
+        self.current_facts
     }
 }
 #[derive(Debug, Clone)]
@@ -119,6 +455,15 @@ pub struct StatedRule {
 pub enum GraphNode {
     Rule(StatedRule),
     Fact(StatedFact),
+    //Группа {a | b} правила, раскрашивается как само правило.
+    Or(StatedRule),
+    Tag(String),
+}
+//Ребро графа: обычное или от катализатора, который не тратится.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphEdge {
+    Normal,
+    Catalyst,
 }
 #[derive(Debug, Clone, Default)]
 pub struct NodeColoring {
@@ -143,3 +488,151 @@ pub enum RuleState {
     VisitedPath,
     DeadEnd,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn fact(e: &Engine, name: &str) -> Fact {
+        let f = e.all_possible_facts.iter().find(|x| x.to_string() == name);
+        f.unwrap().clone()
+    }
+
+    #[test]
+    fn byproducts_are_derived() {
+        let mut e = Engine::from_string("{ore} -> iron_ingot, slag\n{slag} -> gravel\n").unwrap();
+        e.starting_facts.insert(fact(&e, "ore"));
+        let mut d = DirectReasoning::new(&e, fact(&e, "gravel"));
+        assert!(matches!(d.try_find(), StepResult::FoundAfter(..)));
+    }
+
+    fn run(src: &str, target: &str) -> StepResult {
+        let e = Engine::from_string(src).unwrap();
+        DirectReasoning::new(&e, fact(&e, target)).try_find()
+    }
+
+    #[test]
+    fn negation_waits_for_lower_strata() {
+        let src = "{water, !frozen} -> liquid_water\n{cold} -> frozen\n";
+        let res = run(&format!("{}start: water\n", src), "liquid_water");
+        assert!(matches!(res, StepResult::FoundAfter(..)));
+        let res = run(&format!("{}start: water, cold\n", src), "liquid_water");
+        assert_eq!(res, StepResult::NotProved);
+    }
+
+    #[test]
+    fn any_group_member_is_enough() {
+        let src = "{coal | charcoal, stick} -> torch\n";
+        let res = run(&format!("{}start: charcoal, stick\n", src), "torch");
+        assert!(matches!(res, StepResult::FoundAfter(..)));
+        let res = run(&format!("{}start: stick\n", src), "torch");
+        assert_eq!(res, StepResult::NotProved);
+    }
+
+    #[test]
+    fn rules_are_grounded_as_facts_come_in() {
+        let src = "{ore(X)} -> ingot(X)\n{ingot(X)} -> plate(X)\nstart: ore(iron), ore(gold)\n";
+        let e = Engine::from_string(src).unwrap();
+        let target = Arc::new(parser::parse_fact("plate(gold)").unwrap());
+        let res = DirectReasoning::new(&e, target).try_find();
+        assert!(matches!(res, StepResult::FoundAfter(..)));
+        let target = Arc::new(parser::parse_fact("plate(tin)").unwrap());
+        let res = DirectReasoning::new(&e, target).try_find();
+        assert_eq!(res, StepResult::NotProved);
+    }
+
+    #[test]
+    fn any_tag_member_is_enough() {
+        let src = "tag planks = oak, birch\n{2 #planks} -> stick\n";
+        let res = run(&format!("{}start: birch\n", src), "stick");
+        assert!(matches!(res, StepResult::FoundAfter(..)));
+    }
+
+    //Выходы правил в порядке срабатывания.
+    fn firing_order(src: &str, strategy: ConflictStrategy) -> Vec<String> {
+        let e = Engine::from_string(src).unwrap();
+        let mut d = DirectReasoning::with_strategy(&e, vec![fact(&e, "never")], strategy);
+        let mut res = vec![];
+        while let StepResult::Applied(r) = d.step() {
+            res.push(r.outs[0].fact.to_string());
+        }
+        res
+    }
+
+    #[test]
+    fn strategies_choose_in_order() {
+        let src = "never\n{a} -> x1\n{a, b, c} -> x3\n{a, b} -> x2 @priority=5\n{x1} -> y\n\
+                   start: a, b, c\n";
+        let order = |s| firing_order(src, s);
+        assert_eq!(
+            order(ConflictStrategy::DeclarationOrder),
+            ["x1", "x3", "x2", "y"]
+        );
+        assert_eq!(
+            order(ConflictStrategy::FewestRequirements),
+            ["x1", "y", "x2", "x3"]
+        );
+        assert_eq!(
+            order(ConflictStrategy::MostRequirements),
+            ["x3", "x2", "x1", "y"]
+        );
+        assert_eq!(order(ConflictStrategy::Priority), ["x2", "x1", "x3", "y"]);
+        //Из правил на одних и тех же фактах раньше срабатывает более частное.
+        assert_eq!(order(ConflictStrategy::Recency), ["x3", "x2", "x1", "y"]);
+        let src = "never\n{a} -> b\n{a} -> c\n{c} -> d\n{b} -> e\nstart: a\n";
+        let order = |s| firing_order(src, s);
+        assert_eq!(
+            order(ConflictStrategy::DeclarationOrder),
+            ["b", "c", "d", "e"]
+        );
+        assert_eq!(order(ConflictStrategy::Recency), ["b", "e", "c", "d"]);
+    }
+
+    #[test]
+    fn random_is_reproducible() {
+        let mut src = String::from("never\n");
+        for i in 0..20 {
+            src += &format!("{{a}} -> x{}\n", i);
+        }
+        src += "start: a\n";
+        let first = firing_order(&src, ConflictStrategy::Random(7));
+        assert_eq!(first, firing_order(&src, ConflictStrategy::Random(7)));
+        assert_ne!(first, firing_order(&src, ConflictStrategy::Random(8)));
+        let mut sorted = first.clone();
+        sorted.sort();
+        let mut declared = firing_order(&src, ConflictStrategy::DeclarationOrder);
+        assert_ne!(first, declared);
+        declared.sort();
+        assert_eq!(sorted, declared);
+    }
+
+    #[test]
+    fn pruning_skips_irrelevant_rules() {
+        let src = "{sand} -> glass\n{glass} -> bottle\n{wood} -> plank\n{rain} -> wet\n\
+                   {plank, !wet} -> stick\n{plank, stick} -> pickaxe\n";
+        for start in ["start: sand, wood\n", "start: sand, wood, rain\n"] {
+            let e = Engine::from_string(&format!("{}{}", src, start)).unwrap();
+            let mut full = DirectReasoning::new(&e, fact(&e, "pickaxe"));
+            let mut pruned = full.clone();
+            pruned.prune(&e);
+            assert!(pruned.remaining_firings() < full.remaining_firings());
+            assert_eq!(pruned.try_find(), full.try_find());
+            assert_eq!(pruned.proof(), full.proof());
+        }
+    }
+
+    #[test]
+    fn stops_when_all_targets_are_derived() {
+        let e =
+            Engine::from_string("z\n{a} -> b\n{b} -> c\n{a} -> d\n{d} -> e\nstart: a\n").unwrap();
+        let mut d = DirectReasoning::with_targets(&e, vec![fact(&e, "d"), fact(&e, "b")]);
+        let StepResult::FoundAfter(_, last) = d.try_find() else {
+            panic!("targets not found");
+        };
+        assert_eq!(last, fact(&e, "d"));
+        assert_eq!(d.proof_of(&fact(&e, "b")).unwrap().rules().len(), 1);
+        let mut d = DirectReasoning::with_targets(&e, vec![fact(&e, "b"), fact(&e, "z")]);
+        assert_eq!(d.try_find(), StepResult::NotProved);
+    }
+}
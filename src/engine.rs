@@ -1,23 +1,38 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::{Arc, Mutex, RwLock},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
 };
 
 use egui::Vec2;
 use egui_graphs::Graph;
-use petgraph::{stable_graph::StableGraph, Directed};
-use regex::Regex;
+use petgraph::{algo::tarjan_scc, graphmap::DiGraphMap, stable_graph::StableGraph, Directed};
 
 use crate::{
-    direct_reasoning::{self, FactState, GraphNode, NodeColoring, StatedFact, StatedRule},
-    fact::{CoreFact, CoreRule, Fact, Rule},
-    ruletree::RuleTree,
+    abduction,
+    derivations::Derivations,
+    direct_reasoning::{
+        self, DirectReasoning, FactState, GraphEdge, GraphNode, NodeColoring, StatedFact,
+        StatedRule, StepResult,
+    },
+    fact::{CoreFact, CoreRule, Fact, Rule, Tag},
+    formats,
+    inventory::{Crafter, Inventory},
+    lint::{self, LintWarning},
+    minecraft,
+    parser::{self, ParseError, SourceLoader},
+    plan::Plan,
+    reverse_reasoning::{RevStepResult, ReverseReasoning},
+    ruletree::{RuleTree, Source},
+    unify,
 };
 #[derive(Debug, Clone)]
 pub struct Engine {
     pub starting_facts: HashSet<Fact>,
     pub all_possible_facts: Vec<Fact>,
     pub rules: Vec<Rule>,
+    pub goals: Vec<Fact>,
+    pub tags: Vec<Tag>,
 }
 
 impl Engine {
@@ -36,85 +51,143 @@ impl Engine {
         ];
         Self::new(possible, starting, rules)
     }
-    pub fn from_string(s: &str) -> Self {
-        let mut facts_vec = vec![];
-        let mut facts: HashMap<&str, Fact> = HashMap::new();
-        let mut rules = vec![];
-        let comment = Regex::new(r#"//.*"#).unwrap();
-        //let rule = Regex::new(r#"\{(?:(\w+)\s*,\s*)*(?:(\w+)\s*)\}\s*->\s*(\w+)"#).unwrap();
-        let rule = Regex::new(r#"\{(.*)\} \s*->\s*(\w+)"#).unwrap();
-        let fact = Regex::new(r#"(\w+)"#).unwrap();
-        for i in s.lines() {
-            if comment.is_match(i) {
-                continue;
-            }
-            if i.trim().is_empty() {
-                continue;
-            }
-            if let Some(capt) = rule.captures(i) {
-                let l = capt.len();
-                let res = l - 1;
-                let last_req = res - 1;
-                let res_str = capt.get(res).unwrap().as_str().trim();
-
-                let res_fact = if facts.contains_key(res_str) {
-                    facts.get(res_str).unwrap().clone()
-                } else {
-                    facts_vec.push(res_str);
-                    let f = CoreFact::new(res_str);
-                    facts.insert(res_str, f.clone());
-                    f
-                };
-                let mut v = vec![];
-                let reqs = capt.get(last_req).unwrap().as_str();
-                for t in fact.captures_iter(reqs) {
-                    for req in t.get(0) {
-                        let req_str = req.as_str().trim();
-                        let req_fact = if facts.contains_key(req_str) {
-                            facts.get(req_str).unwrap().clone()
-                        } else {
-                            facts_vec.push(req_str);
-                            let f = CoreFact::new(req_str);
-                            facts.insert(req_str, f.clone());
-                            f
-                        };
-
-                        v.push(req_fact);
-                    }
-                }
-                let rule = CoreRule::new(v.into_iter(), res_fact);
-                rules.push(rule);
-            } else if let Some(capt) = fact.captures(i) {
-                let fact_str = capt.get(1).unwrap().as_str().trim();
-                if !facts.contains_key(fact_str) {
-                    facts_vec.push(fact_str);
-                    let f = CoreFact::new(fact_str);
-                    facts.insert(fact_str, f.clone());
-                };
-            } else {
-                continue;
-            }
-        }
-        //facts_vec.dedup();
-        let f = facts_vec
-            .iter()
-            .map(|x| facts.get(x).unwrap())
-            .cloned()
-            .collect();
-        Engine {
-            starting_facts: HashSet::new(),
-            all_possible_facts: f,
-            rules,
-        }
+    pub fn from_string(s: &str) -> Result<Self, Vec<ParseError>> {
+        parser::parse(s)
+    }
+    //Формат выбирается по расширению файла, всё незнакомое читается как правила.
+    pub fn from_file(path: &Path, loader: &dyn SourceLoader) -> Result<Self, Vec<ParseError>> {
+        let from: fn(&str) -> Result<Self, Vec<ParseError>> =
+            match path.extension().and_then(|x| x.to_str()) {
+                Some("json") => Self::from_json,
+                Some("toml") => Self::from_toml,
+                _ => return parser::parse_file(path, loader),
+            };
+        let with_file = |mut e: ParseError| {
+            e.file = Some(path.to_path_buf());
+            e
+        };
+        let s = loader.load(path).map_err(|e| {
+            vec![with_file(ParseError {
+                file: None,
+                line: 0,
+                column: 0,
+                message: e,
+            })]
+        })?;
+        from(&s).map_err(|es| es.into_iter().map(with_file).collect())
+    }
+    //Рецепты из data pack: путь файла -> содержимое.
+    pub fn from_recipes(files: &HashMap<PathBuf, String>) -> Result<Self, Vec<ParseError>> {
+        minecraft::import_recipes(files)
+    }
+    pub fn from_json(s: &str) -> Result<Self, Vec<ParseError>> {
+        formats::from_json(s)
+    }
+    pub fn to_json(&self) -> String {
+        formats::to_json(self)
+    }
+    pub fn from_toml(s: &str) -> Result<Self, Vec<ParseError>> {
+        formats::from_toml(s)
+    }
+    pub fn to_toml(&self) -> String {
+        formats::to_toml(self)
     }
     pub fn new(all_facts: Vec<Fact>, starting_facts: HashSet<Fact>, rules: Vec<Rule>) -> Self {
         Self {
             starting_facts,
             all_possible_facts: all_facts,
             rules,
+            goals: vec![],
+            tags: vec![],
         }
     }
-    pub fn to_graph(&self) -> (Graph<GraphNode, (), Directed>, NodeColoring) {
+    //Текст, который parser разберёт обратно в такой же Engine (с тем же порядком фактов).
+    pub fn to_rule_string(&self) -> String {
+        let mut starting: Vec<_> = self
+            .all_possible_facts
+            .iter()
+            .filter(|x| self.starting_facts.contains(*x))
+            .cloned()
+            .collect();
+        let mut extra: Vec<_> = self
+            .starting_facts
+            .iter()
+            .filter(|x| !starting.contains(x))
+            .cloned()
+            .collect();
+        extra.sort_by_key(|x| x.to_string());
+        starting.extend(extra);
+
+        let mut mentioned: HashMap<Fact, usize> = HashMap::new();
+        //Варианты #tag в тексте правила не упоминаются: они уже есть в объявлении тега.
+        let body = self
+            .tags
+            .iter()
+            .flat_map(|x| x.members.iter())
+            .chain(self.rules.iter().flat_map(|r| {
+                r.reqs
+                    .iter()
+                    .map(|x| &x.fact)
+                    .chain(
+                        r.groups
+                            .iter()
+                            .filter(|g| g.tag.is_none())
+                            .flatten()
+                            .map(|x| &x.fact),
+                    )
+                    .chain(r.catalysts.iter())
+                    .chain(r.negs.iter())
+                    .chain(r.outs.iter().map(|x| &x.fact))
+            }))
+            .chain(starting.iter())
+            .chain(self.goals.iter());
+        for f in body {
+            let n = mentioned.len();
+            mentioned.entry(f.clone()).or_insert(n);
+        }
+        //Самый длинный хвост фактов, который и так появится в нужном порядке; остальные объявляем явно.
+        let mut declared = self.all_possible_facts.len();
+        let mut next = usize::MAX;
+        while declared > 0 {
+            match mentioned.get(&self.all_possible_facts[declared - 1]) {
+                Some(&i) if i < next => {
+                    next = i;
+                    declared -= 1;
+                }
+                _ => break,
+            }
+        }
+
+        let mut s = String::new();
+        for f in &self.all_possible_facts[..declared] {
+            s += &format!("{}\n", f);
+        }
+        if declared != 0 {
+            s += "\n";
+        }
+        for tag in &self.tags {
+            s += &format!("{}\n", tag);
+        }
+        if !self.tags.is_empty() {
+            s += "\n";
+        }
+        for r in &self.rules {
+            s += &format!("{}\n", r);
+        }
+        if !self.rules.is_empty() {
+            s += "\n";
+        }
+        if !starting.is_empty() {
+            let names: Vec<_> = starting.iter().map(|x| x.to_string()).collect();
+            s += &format!("start: {}\n", names.join(", "));
+        }
+        if !self.goals.is_empty() {
+            let names: Vec<_> = self.goals.iter().map(|x| x.to_string()).collect();
+            s += &format!("goal: {}\n", names.join(", "));
+        }
+        s
+    }
+    pub fn to_stable_graph(&self) -> (StableGraph<GraphNode, GraphEdge, Directed>, NodeColoring) {
         let mut coloring_facts = Arc::new(RwLock::new(HashMap::new()));
         let mut coloring_rules = Arc::new(RwLock::new(HashMap::new()));
 
@@ -147,6 +220,15 @@ impl Engine {
 
             nodes.insert(f.clone(), node);
         }
+        //Узел тега один на всю базу: от членов к нему и от него ко всем правилам с #tag.
+        let mut tags = HashMap::new();
+        for tag in &self.tags {
+            let ind = g.add_node(GraphNode::Tag(tag.name.clone()));
+            for member in &tag.members {
+                g.add_edge(nodes[member], ind, GraphEdge::Normal);
+            }
+            tags.insert(tag.name.clone(), ind);
+        }
         for r in &self.rules {
             let rule = GraphNode::Rule(StatedRule {
                 rule: r.clone(),
@@ -157,18 +239,49 @@ impl Engine {
                 .unwrap()
                 .insert(r.clone(), direct_reasoning::RuleState::None);
             let rule_ind = g.add_node(rule);
-            let outind = nodes[&r.out];
-            g.add_edge(rule_ind, outind, ());
+            for out in &r.outs {
+                let outind = nodes[&out.fact];
+                g.add_edge(rule_ind, outind, GraphEdge::Normal);
+            }
 
             for reqs in &r.reqs {
-                let inind = nodes[reqs];
-                g.add_edge(inind, rule_ind, ());
+                let inind = nodes[&reqs.fact];
+                g.add_edge(inind, rule_ind, GraphEdge::Normal);
+            }
+            //Группа {a | b} рисуется отдельным маленьким узлом перед правилом.
+            for group in &r.groups {
+                if let Some(&tag_ind) = group.tag.as_ref().and_then(|x| tags.get(x)) {
+                    g.add_edge(tag_ind, rule_ind, GraphEdge::Normal);
+                    continue;
+                }
+                let or_ind = g.add_node(GraphNode::Or(StatedRule {
+                    rule: r.clone(),
+                    state: coloring_rules.clone(),
+                }));
+                for option in group {
+                    g.add_edge(nodes[&option.fact], or_ind, GraphEdge::Normal);
+                }
+                g.add_edge(or_ind, rule_ind, GraphEdge::Normal);
+            }
+            for catalyst in &r.catalysts {
+                g.add_edge(nodes[catalyst], rule_ind, GraphEdge::Catalyst);
+            }
+            for neg in &r.negs {
+                g.add_edge(nodes[neg], rule_ind, GraphEdge::Normal);
             }
         }
+        (
+            g,
+            NodeColoring {
+                facts: coloring_facts,
+                rules: coloring_rules,
+            },
+        )
+    }
+    pub fn to_graph(&self) -> (Graph<GraphNode, GraphEdge, Directed>, NodeColoring) {
+        let (g, coloring) = self.to_stable_graph();
         let t = (g.node_count() as f32).sqrt().round() as usize;
         let mut gr: Graph<_, _, _> = (&g).into();
-        let mut v: Vec<_> = nodes.values().collect();
-        v.sort();
         for (i, ind) in g.node_indices().enumerate() {
             let node = gr.node_mut(ind).unwrap();
             node.set_location(Vec2 {
@@ -176,15 +289,9 @@ impl Engine {
                 y: ((i / t) * 50) as _,
             })
         }
-        (
-            gr,
-            NodeColoring {
-                facts: coloring_facts,
-                rules: coloring_rules,
-            },
-        )
+        (gr, coloring)
     }
-    pub fn recolor_node(&self, target: Option<Fact>, coloring: &NodeColoring) {
+    pub fn recolor_node(&self, targets: &[Fact], coloring: &NodeColoring) {
         for f in &self.all_possible_facts {
             let f = f.clone();
             if self.starting_facts.contains(&f) {
@@ -208,25 +315,373 @@ impl Engine {
                 .unwrap()
                 .insert(r.clone(), direct_reasoning::RuleState::None);
         }
-        if let Some(fact) = target {
+        for fact in targets {
             coloring
                 .facts
                 .write()
                 .unwrap()
-                .insert(fact, FactState::Target);
+                .insert(fact.clone(), FactState::Target);
         }
     }
-    fn try_direct_output(&self, target_fact: Fact) -> Option<RuleTree> {
-        let mut appliedRules: HashSet<Rule> = HashSet::new();
-        let current_facts: HashSet<Fact> = self.starting_facts.iter().cloned().collect();
-        while appliedRules.len() < self.rules.len() {
-            if current_facts.contains(&target_fact) {
-                todo!()
+    //Подсвечивает дерево вывода как VisitedPath, остальное как в начальном состоянии.
+    //Частные случаи правил с переменными подсвечивают само правило.
+    pub fn recolor_tree(&self, tree: &RuleTree, coloring: &NodeColoring) {
+        self.recolor_node(&[], coloring);
+        let schemas: HashMap<Rule, Rule> = self.ground_instances().into_iter().collect();
+        let mut facts = coloring.facts.write().unwrap();
+        let mut rules = coloring.rules.write().unwrap();
+        let mut stack = vec![tree];
+        while let Some(t) = stack.pop() {
+            facts.insert(t.fact.clone(), FactState::VisitedPath);
+            if let Some(rule) = &t.rule {
+                let rule = schemas.get(rule).unwrap_or(rule);
+                rules.insert(rule.clone(), direct_reasoning::RuleState::VisitedPath);
+            }
+            for s in &t.sources {
+                if let Source::Rule(t) = s {
+                    stack.push(t);
+                }
             }
         }
-        None
+        facts.insert(tree.fact.clone(), FactState::TargetVisited);
     }
-    fn try_reverse_output(&self, fact: Fact) -> Option<RuleTree> {
-        todo!()
+    //Страты для вывода с отрицанием: правило попадает в страту выше всех фактов,
+    //отсутствие которых оно проверяет. Цикл через отрицание - ошибка.
+    //Факты с переменными сравниваются по предикату: ingot(X) и ingot(iron) - одно и то же.
+    pub fn strata(&self) -> Result<HashMap<Rule, usize>, String> {
+        let mut predicates: Vec<(&str, usize)> = vec![];
+        let mut index: HashMap<(&str, usize), usize> = HashMap::new();
+        for f in &self.all_possible_facts {
+            index.entry(f.predicate()).or_insert_with(|| {
+                predicates.push(f.predicate());
+                predicates.len() - 1
+            });
+        }
+        //Вес ребра - есть ли между предикатами хоть одно отрицание.
+        let mut g = DiGraphMap::<usize, bool>::new();
+        for r in &self.rules {
+            for out in &r.outs {
+                let to = index[&out.fact.predicate()];
+                let from = r
+                    .reqs
+                    .iter()
+                    .chain(r.groups.iter().flatten())
+                    .map(|x| (&x.fact, false))
+                    .chain(r.catalysts.iter().map(|x| (x, false)));
+                for (f, negated) in from.chain(r.negs.iter().map(|x| (x, true))) {
+                    let from = index[&f.predicate()];
+                    let negated = negated || g.edge_weight(from, to) == Some(&true);
+                    g.add_edge(from, to, negated);
+                }
+            }
+        }
+        for scc in tarjan_scc(&g) {
+            let negated = scc
+                .iter()
+                .flat_map(|&a| scc.iter().map(move |&b| (a, b)))
+                .any(|(a, b)| g.edge_weight(a, b) == Some(&true));
+            if negated {
+                let mut scc = scc;
+                scc.sort();
+                let names: Vec<_> = scc
+                    .iter()
+                    .map(|&i| match predicates[i] {
+                        (name, 0) => name.to_string(),
+                        (name, n) => format!("{}/{}", name, n),
+                    })
+                    .collect();
+                return Err(format!("cycle through negation: {}", names.join(", ")));
+            }
+        }
+        let mut predicate_strata = vec![0; predicates.len()];
+        let mut strata = HashMap::new();
+        loop {
+            let mut changed = false;
+            for r in &self.rules {
+                let get = |x: &Fact| predicate_strata[index[&x.predicate()]];
+                let s = r
+                    .reqs
+                    .iter()
+                    .chain(r.groups.iter().flatten())
+                    .map(|x| get(&x.fact))
+                    .chain(r.catalysts.iter().map(get))
+                    .chain(r.negs.iter().map(|x| get(x) + 1))
+                    .max()
+                    .unwrap_or(0);
+                strata.insert(r.clone(), s);
+                for out in &r.outs {
+                    let old = &mut predicate_strata[index[&out.fact.predicate()]];
+                    if *old < s {
+                        *old = s;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return Ok(strata);
+            }
+        }
+    }
+    //Правила без переменных: правила с переменными подставляются для всех известных констант.
+    pub fn ground_rules(&self) -> Vec<Rule> {
+        self.ground_instances().into_iter().map(|x| x.0).collect()
+    }
+    //То же вместе с исходными правилами: частный случай -> правило с переменными.
+    pub fn ground_instances(&self) -> Vec<(Rule, Rule)> {
+        let constants =
+            unify::constants(self.all_possible_facts.iter().chain(&self.starting_facts));
+        let mut res = vec![];
+        let mut seen = HashSet::new();
+        for r in &self.rules {
+            if r.is_ground() {
+                res.push((r.clone(), r.clone()));
+                continue;
+            }
+            for b in unify::ground(r, Default::default(), &constants) {
+                let i = unify::instantiate(r, &b);
+                if seen.insert(i.clone()) {
+                    res.push((i, r.clone()));
+                }
+            }
+        }
+        res
+    }
+    //Все выводимые факты. Правила нижних страт срабатывают первыми, так что отрицания
+    //проверяются только когда всё, что может дать отрицаемый факт, уже сработало.
+    pub fn model(&self) -> HashSet<Fact> {
+        DirectReasoning::with_targets(self, vec![]).model()
+    }
+    //Правила, которые могут участвовать в выводе target: обратная достижимость от цели.
+    //Правила для фактов под отрицанием тоже нужны - от них зависит, сработает ли правило.
+    //Факты с переменными сравниваются по предикату, с запасом.
+    pub fn relevant_rules(&self, target: &Fact) -> HashSet<Rule> {
+        let same = |a: &Fact, b: &Fact| {
+            if a.is_ground() && b.is_ground() {
+                a == b
+            } else {
+                a.predicate() == b.predicate()
+            }
+        };
+        let mut relevant = HashSet::new();
+        let mut seen: HashSet<Fact> = HashSet::new();
+        let mut queue = vec![target.clone()];
+        while let Some(f) = queue.pop() {
+            if !seen.insert(f.clone()) {
+                continue;
+            }
+            for r in &self.rules {
+                if relevant.contains(r) || !r.outs.iter().any(|x| same(&x.fact, &f)) {
+                    continue;
+                }
+                relevant.insert(r.clone());
+                queue.extend(
+                    r.reqs
+                        .iter()
+                        .chain(r.groups.iter().flatten())
+                        .map(|x| x.fact.clone())
+                        .chain(r.catalysts.iter().cloned())
+                        .chain(r.negs.iter().cloned()),
+                );
+            }
+        }
+        relevant
+    }
+    pub fn lint(&self) -> Vec<LintWarning> {
+        lint::lint(self)
+    }
+    pub fn max_craftable(&self, target: &Fact, inventory: &Inventory) -> u32 {
+        Crafter::new(&self.ground_rules()).max_craftable(target, inventory)
+    }
+    //Дерево вывода target до начальных фактов. Для начального факта - лист без правила.
+    pub fn prove(&self, target: Fact, mode: ProofMode) -> Option<RuleTree> {
+        if self.starting_facts.contains(&target) {
+            return Some(RuleTree {
+                fact: target,
+                rule: None,
+                sources: vec![],
+            });
+        }
+        match mode {
+            ProofMode::Forward => {
+                let mut d = DirectReasoning::new(self, target);
+                loop {
+                    match d.step() {
+                        StepResult::Applied(_) => (),
+                        StepResult::Found | StepResult::FoundAfter(..) => break d.proof(),
+                        StepResult::NotProved => break None,
+                    }
+                }
+            }
+            ProofMode::Backward => {
+                let mut r = ReverseReasoning::new(self, target);
+                loop {
+                    match r.step() {
+                        RevStepResult::Iterated => (),
+                        RevStepResult::Found => break r.proof(),
+                        RevStepResult::NotProved => break None,
+                    }
+                }
+            }
+        }
+    }
+    //Все различные выводы target, не больше limit.
+    pub fn derivations(&self, target: Fact, limit: usize, dedup: bool) -> Derivations {
+        Derivations::new(self, target, limit, dedup)
+    }
+    //Минимальные наборы сырых фактов, которых не хватает для вывода target.
+    //Если target уже выводится, единственный набор пуст.
+    pub fn missing_starting_facts(
+        &self,
+        target: &Fact,
+        limit: usize,
+        smallest: bool,
+    ) -> Vec<Vec<Fact>> {
+        abduction::missing_starting_facts(self, target, limit, smallest)
+    }
+    //Самый дешёвый по сумме @cost вывод target.
+    pub fn cheapest_plan(&self, target: &Fact) -> Option<Plan> {
+        Plan::cheapest(self, target)
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofMode {
+    Forward,  //Прямой вывод от начальных фактов.
+    Backward, //Обратный вывод от цели.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats;
+
+    fn assert_same(a: &Engine, b: &Engine) {
+        assert_eq!(a.all_possible_facts, b.all_possible_facts);
+        assert_eq!(a.starting_facts, b.starting_facts);
+        assert_eq!(a.rules, b.rules);
+        assert_eq!(a.goals, b.goals);
+        assert_eq!(a.tags, b.tags);
+    }
+
+    fn round_trip(src: &str) -> Engine {
+        let e = Engine::from_string(src).unwrap();
+        assert_same(&e, &Engine::from_string(&e.to_rule_string()).unwrap());
+        assert_same(&e, &formats::from_json(&formats::to_json(&e)).unwrap());
+        assert_same(&e, &formats::from_toml(&formats::to_toml(&e)).unwrap());
+        e
+    }
+
+    #[test]
+    fn crafts_round_trip() {
+        round_trip(include_str!("crafts.txt"));
+    }
+
+    #[test]
+    fn unused_and_starting_facts_are_kept() {
+        let e = round_trip("lonely\n{2 log} -> 8 plank, bark\nstart: log, sand\ngoal: plank\n");
+        let text = e.to_rule_string();
+        assert!(text.contains("lonely"));
+        assert!(text.contains("{2 log} -> 8 plank, bark"));
+        assert!(text.contains("start: log, sand"));
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        let e = round_trip(
+            "smelt: {ore} -> ingot @machine=furnace @time=10\n\
+             {ingot} -> plate @note=\"two words\"\n",
+        );
+        assert_eq!(e.rules[0].name.as_deref(), Some("smelt"));
+        assert_eq!(e.rules[1].attr("note"), Some("two words"));
+    }
+
+    #[test]
+    fn cycle_through_negation_is_rejected() {
+        let e = Engine::from_string("{a, !b} -> c\n{c} -> b\n").unwrap_err();
+        assert_eq!(e[0].message, "cycle through negation: b, c");
+        let e = round_trip("{a, !b} -> c\n{c} -> d\n{d} -> c\n{a} -> b\nstart: a\n");
+        let strata = e.strata().unwrap();
+        assert_eq!(strata[&e.rules[0]], 1);
+        assert_eq!(strata[&e.rules[3]], 0);
+    }
+
+    #[test]
+    fn group_is_one_or_node() {
+        let e = round_trip("{coal | charcoal, stick} -> torch\n{coal | charcoal} -> 2 fuel\n");
+        let (g, _) = e.to_stable_graph();
+        let or = g
+            .node_weights()
+            .filter(|x| matches!(x, GraphNode::Or(_)))
+            .count();
+        assert_eq!(or, 2);
+        assert_eq!(g.node_count(), 5 + 2 + 2);
+    }
+
+    #[test]
+    fn variables_round_trip() {
+        let e = round_trip("{ingot(X), !rusty(X)} -> plate(X)\nstart: ingot(iron)\n");
+        assert!(!e.rules[0].is_ground());
+        let e = Engine::from_string("{ingot(X)} -> plate(Y)\n").unwrap_err();
+        assert_eq!(e[0].message, "variable `Y` is not bound by a requirement");
+    }
+
+    #[test]
+    fn tag_node_links_members_to_rules() {
+        let e =
+            round_trip("tag planks = oak, birch\n{2 #planks} -> 4 stick\n{#planks} -> button\n");
+        let (g, _) = e.to_stable_graph();
+        let tags: Vec<_> = g
+            .node_indices()
+            .filter(|&i| matches!(g[i], GraphNode::Tag(_)))
+            .collect();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(g.neighbors_directed(tags[0], petgraph::Incoming).count(), 2);
+        assert_eq!(g.neighbors_directed(tags[0], petgraph::Outgoing).count(), 2);
+    }
+
+    #[test]
+    fn catalyst_edges() {
+        let e = round_trip("{iron_ore, @furnace} -> iron_ingot\n{@furnace, @anvil} -> repair\n");
+        assert_eq!(e.rules[0].catalysts.len(), 1);
+        assert_eq!(e.rules[0].reqs.len(), 1);
+        let (g, _) = e.to_stable_graph();
+        let catalysts = g.edge_weights().filter(|x| **x == GraphEdge::Catalyst);
+        assert_eq!(catalysts.count(), 3);
+    }
+
+    fn fact(e: &Engine, name: &str) -> Fact {
+        let f = e.all_possible_facts.iter().find(|x| x.to_string() == name);
+        f.unwrap().clone()
+    }
+
+    const PICKAXE: &str = "{wood} -> 4 plank\n{2 plank} -> 4 stick\n{sand} -> glass\n\
+                           {3 plank, 2 stick} -> pickaxe\n{iron} -> sword\nstart: wood, sand\n";
+
+    #[test]
+    fn prove_gives_same_tree_in_both_modes() {
+        let e = Engine::from_string(PICKAXE).unwrap();
+        let forward = e.prove(fact(&e, "pickaxe"), ProofMode::Forward).unwrap();
+        let backward = e.prove(fact(&e, "pickaxe"), ProofMode::Backward).unwrap();
+        assert_eq!(forward, backward);
+        let rules: Vec<String> = forward.rules().iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            rules,
+            [
+                "{wood} -> 4 plank",
+                "{wood} -> 4 plank",
+                "{2 plank} -> 4 stick",
+                "{3 plank, 2 stick} -> pickaxe"
+            ]
+        );
+        let leaf = RuleTree {
+            fact: fact(&e, "wood"),
+            rule: None,
+            sources: vec![],
+        };
+        for mode in [ProofMode::Forward, ProofMode::Backward] {
+            assert_eq!(e.prove(fact(&e, "sword"), mode), None);
+            assert_eq!(e.prove(fact(&e, "wood"), mode), Some(leaf.clone()));
+        }
+        assert!(leaf.rules().is_empty());
+        assert_eq!(leaf.to_string(), "wood\n");
     }
 }
@@ -1,9 +1,11 @@
 use core::fmt;
 use std::{
-    collections::{HashMap, HashSet},
-    sync::{Arc, RwLock},
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
 };
 
+use crate::parser;
+
 // #[derive(Debug, Clone)]
 // pub struct ConcreteRule {
 //     input: Vec<Arc<CoreFact>>,
@@ -11,57 +13,250 @@ use std::{
 // }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CoreRule {
-    pub reqs: Vec<Fact>,
-    pub out: Fact, //Правило вывода одного конкретного факта из множества.
+    pub name: Option<String>,
+    pub reqs: Vec<Stack>,
+    pub groups: Vec<Group>,
+    pub catalysts: Vec<Fact>, //Нужны, но не тратятся: {iron_ore, @furnace}.
+    pub negs: Vec<Fact>,      //Факты, которых не должно быть: {water, !frozen}.
+    pub outs: Vec<Stack>,     //Правило вывода фактов (основного и побочных) из множества.
+    pub attrs: BTreeMap<String, String>, //@machine=furnace и т.п.
 }
 pub type Fact = Arc<CoreFact>;
+//Факт вместе с количеством: сколько нужно для правила или сколько получается.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Stack {
+    pub fact: Fact,
+    pub count: u32,
+}
+impl Stack {
+    pub fn new(fact: Fact, count: u32) -> Self {
+        Stack { fact, count }
+    }
+}
+impl From<Fact> for Stack {
+    fn from(fact: Fact) -> Self {
+        Stack::new(fact, 1)
+    }
+}
+//Требование, которому хватает любого из вариантов: {coal | charcoal} или #planks.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Group {
+    pub tag: Option<String>,
+    pub options: Vec<Stack>,
+}
+impl Group {
+    pub fn new(options: Vec<Stack>) -> Self {
+        Group { tag: None, options }
+    }
+    pub fn tagged(tag: &Tag, count: u32) -> Self {
+        Group {
+            tag: Some(tag.name.clone()),
+            options: tag
+                .members
+                .iter()
+                .map(|x| Stack::new(x.clone(), count))
+                .collect(),
+        }
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, Stack> {
+        self.options.iter()
+    }
+}
+impl<'a> IntoIterator for &'a Group {
+    type Item = &'a Stack;
+    type IntoIter = std::slice::Iter<'a, Stack>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+//tag planks = oak_planks, birch_planks
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tag {
+    pub name: String,
+    pub members: Vec<Fact>,
+}
 impl CoreRule {
     pub fn match_requirement(&self, facts: &HashSet<Fact>) -> bool {
-        self.reqs.iter().all(|y| facts.contains(y))
+        self.reqs.iter().all(|y| facts.contains(&y.fact))
+            && self.catalysts.iter().all(|y| facts.contains(y))
+            && self
+                .groups
+                .iter()
+                .all(|g| g.iter().any(|y| facts.contains(&y.fact)))
+            && !self.negs.iter().any(|y| facts.contains(y))
+    }
+    pub fn produces(&self, fact: &Fact) -> bool {
+        self.outs.iter().any(|x| &x.fact == fact)
+    }
+    //Сколько штук fact получается за одно применение правила.
+    pub fn produced_count(&self, fact: &Fact) -> u32 {
+        self.outs
+            .iter()
+            .filter(|x| &x.fact == fact)
+            .map(|x| x.count)
+            .sum()
     }
     pub fn new(reqs: impl Iterator<Item = Fact>, out: Fact) -> Arc<Self> {
+        Self::with_counts(reqs.map(Stack::from), std::iter::once(out.into()))
+    }
+    pub fn with_counts(
+        reqs: impl Iterator<Item = Stack>,
+        outs: impl Iterator<Item = Stack>,
+    ) -> Arc<Self> {
         Arc::new(CoreRule {
+            name: None,
             reqs: reqs.collect(),
-            out,
+            groups: vec![],
+            catalysts: vec![],
+            negs: vec![],
+            outs: outs.collect(),
+            attrs: BTreeMap::new(),
         })
     }
+    pub fn is_ground(&self) -> bool {
+        self.reqs
+            .iter()
+            .chain(self.groups.iter().flatten())
+            .chain(self.outs.iter())
+            .all(|x| x.fact.is_ground())
+            && self
+                .catalysts
+                .iter()
+                .chain(self.negs.iter())
+                .all(|x| x.is_ground())
+    }
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(|x| x.as_str())
+    }
+    //Стоимость применения из @cost, по умолчанию 1.
+    pub fn cost(&self) -> f64 {
+        self.attr("cost")
+            .and_then(|x| x.parse::<f64>().ok())
+            .filter(|x| x.is_finite() && *x >= 0.0)
+            .unwrap_or(1.0)
+    }
+    //Короткая подпись для графа и списков: имя, если оно есть.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.body().to_string(),
+        }
+    }
+    //Правило без имени и атрибутов.
+    pub fn body(&self) -> RuleBody<'_> {
+        RuleBody(self)
+    }
 }
 pub type Rule = Arc<CoreRule>;
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CoreFact {
     Symbol(String),
-    Symbols(Vec<CoreFact>),
+    Symbols(Vec<CoreFact>), //plate(iron): первый элемент - имя, остальные - аргументы.
+    Var(String),            //Переменная в аргументах: ingot(X).
 }
 impl CoreFact {
     pub fn new(symbol: impl Into<String>) -> Arc<Self> {
         Arc::new(CoreFact::Symbol(symbol.into()))
     }
+    pub fn is_ground(&self) -> bool {
+        match self {
+            CoreFact::Symbol(_) => true,
+            CoreFact::Symbols(v) => v.iter().all(|x| x.is_ground()),
+            CoreFact::Var(_) => false,
+        }
+    }
+    //Имя и число аргументов: ingot(X) и ingot(iron) - один предикат.
+    pub fn predicate(&self) -> (&str, usize) {
+        match self {
+            CoreFact::Symbol(s) | CoreFact::Var(s) => (s, 0),
+            CoreFact::Symbols(v) => match v.first() {
+                Some(CoreFact::Symbol(s)) => (s, v.len() - 1),
+                _ => ("", v.len()),
+            },
+        }
+    }
 }
 
 impl fmt::Display for CoreFact {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            CoreFact::Symbol(s) => write!(f, "{}", s),
+            CoreFact::Symbol(s) | CoreFact::Var(s) => write!(f, "{}", s),
             CoreFact::Symbols(v) => {
-                write!(f, "(")?;
-                for i in v {
-                    write!(f, "{}, ", i)?
+                let Some((name, args)) = v.split_first() else {
+                    return Ok(());
+                };
+                let args: Vec<_> = args.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+impl fmt::Display for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count != 1 {
+            write!(f, "{} ", self.count)?;
+        }
+        write!(f, "{}", self.fact)
+    }
+}
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.tag {
+            Some(tag) => {
+                let count = self.options.first().map_or(1, |x| x.count);
+                if count != 1 {
+                    write!(f, "{} ", count)?;
                 }
-                write!(f, ")")
+                write!(f, "#{}", tag)
+            }
+            None => {
+                let options: Vec<_> = self.options.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}", options.join(" | "))
             }
         }
     }
 }
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let members: Vec<_> = self.members.iter().map(|x| x.to_string()).collect();
+        write!(f, "tag {} = {}", self.name, members.join(", "))
+    }
+}
+pub struct RuleBody<'a>(&'a CoreRule);
 impl fmt::Display for CoreRule {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{{")?;
-        for (i, fact) in self.reqs.iter().enumerate() {
-            if i != self.reqs.len() - 1 {
-                write!(f, "{}, ", fact)?;
+        if let Some(name) = &self.name {
+            write!(f, "{}: ", name)?;
+        }
+        write!(f, "{}", self.body())?;
+        for (k, v) in &self.attrs {
+            if parser::is_valid_name(v) || parser::is_number(v) {
+                write!(f, " @{}={}", k, v)?;
             } else {
-                write!(f, "{} ", fact)?;
+                write!(f, " @{}={}", k, parser::quote(v))?;
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for RuleBody<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rule = self.0;
+        let reqs: Vec<_> = rule
+            .reqs
+            .iter()
+            .map(|x| x.to_string())
+            .chain(rule.groups.iter().map(|g| g.to_string()))
+            .chain(rule.catalysts.iter().map(|x| format!("@{}", x)))
+            .chain(rule.negs.iter().map(|x| format!("!{}", x)))
+            .collect();
+        write!(f, "{{{}}} -> ", reqs.join(", "))?;
+        for (i, out) in rule.outs.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
             }
+            write!(f, "{}", out)?;
         }
-        write!(f, "}} -> {}", self.out)
+        Ok(())
     }
 }
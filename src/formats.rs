@@ -0,0 +1,477 @@
+use core::fmt;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::Engine,
+    fact::{CoreFact, CoreRule, Fact, Group, Stack, Tag},
+    parser::{self, ParseError},
+    unify,
+};
+
+//Описание базы правил для JSON и TOML. Факт с количеством 1 можно писать просто строкой.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StackDef {
+    Name(String),
+    Counted { fact: String, count: u32 },
+}
+//Значения атрибутов хранятся строками, но в файле можно писать числа и bool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum AttrValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttrValue::Str(s) => write!(f, "{}", s),
+            AttrValue::Int(x) => write!(f, "{}", x),
+            AttrValue::Float(x) => write!(f, "{}", x),
+            AttrValue::Bool(x) => write!(f, "{}", x),
+        }
+    }
+}
+//Группа - список вариантов или тег: ["coal", "charcoal"] или "#planks".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum GroupDef {
+    Options(Vec<StackDef>),
+    Tag(StackDef),
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TagDef {
+    name: String,
+    members: Vec<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RuleDef {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default)]
+    reqs: Vec<StackDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<GroupDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    catalysts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    negs: Vec<String>,
+    outs: Vec<StackDef>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    attrs: BTreeMap<String, AttrValue>,
+}
+//Одна цель пишется строкой, несколько - списком.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum GoalDef {
+    One(String),
+    Many(Vec<String>),
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct RuleBase {
+    #[serde(default)]
+    facts: Vec<String>,
+    #[serde(default)]
+    start: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    goal: Option<GoalDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<TagDef>,
+    #[serde(default)]
+    rules: Vec<RuleDef>,
+}
+
+fn error(message: impl Into<String>) -> ParseError {
+    ParseError {
+        file: None,
+        line: 0,
+        column: 0,
+        message: message.into(),
+    }
+}
+
+impl StackDef {
+    fn name(&self) -> &str {
+        match self {
+            StackDef::Name(name) | StackDef::Counted { fact: name, .. } => name,
+        }
+    }
+    fn count(&self) -> u32 {
+        match self {
+            StackDef::Name(_) => 1,
+            StackDef::Counted { count, .. } => *count,
+        }
+    }
+    fn is_tag(&self) -> bool {
+        self.name().starts_with('#')
+    }
+}
+impl From<&Stack> for StackDef {
+    fn from(s: &Stack) -> Self {
+        if s.count == 1 {
+            StackDef::Name(s.fact.to_string())
+        } else {
+            StackDef::Counted {
+                fact: s.fact.to_string(),
+                count: s.count,
+            }
+        }
+    }
+}
+impl From<&Engine> for RuleBase {
+    fn from(e: &Engine) -> Self {
+        RuleBase {
+            facts: e.all_possible_facts.iter().map(|x| x.to_string()).collect(),
+            start: e
+                .all_possible_facts
+                .iter()
+                .filter(|x| e.starting_facts.contains(*x))
+                .map(|x| x.to_string())
+                .collect(),
+            goal: match e.goals.as_slice() {
+                [] => None,
+                [goal] => Some(GoalDef::One(goal.to_string())),
+                goals => Some(GoalDef::Many(goals.iter().map(|x| x.to_string()).collect())),
+            },
+            tags: e
+                .tags
+                .iter()
+                .map(|x| TagDef {
+                    name: x.name.clone(),
+                    members: x.members.iter().map(|x| x.to_string()).collect(),
+                })
+                .collect(),
+            rules: e
+                .rules
+                .iter()
+                .map(|r| RuleDef {
+                    name: r.name.clone(),
+                    reqs: r.reqs.iter().map(StackDef::from).collect(),
+                    groups: r
+                        .groups
+                        .iter()
+                        .map(|g| match &g.tag {
+                            Some(tag) => {
+                                let fact = format!("#{}", tag);
+                                GroupDef::Tag(match g.options.first().map_or(1, |x| x.count) {
+                                    1 => StackDef::Name(fact),
+                                    count => StackDef::Counted { fact, count },
+                                })
+                            }
+                            None => GroupDef::Options(g.iter().map(StackDef::from).collect()),
+                        })
+                        .collect(),
+                    catalysts: r.catalysts.iter().map(|x| x.to_string()).collect(),
+                    negs: r.negs.iter().map(|x| x.to_string()).collect(),
+                    outs: r.outs.iter().map(StackDef::from).collect(),
+                    attrs: r
+                        .attrs
+                        .iter()
+                        .map(|(k, v)| (k.clone(), AttrValue::Str(v.clone())))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    facts: HashMap<CoreFact, Fact>,
+    all_facts: Vec<Fact>,
+    errors: Vec<ParseError>,
+}
+impl Interner {
+    fn fact(&mut self, name: &str) -> Fact {
+        let fact = parser::parse_fact(name).unwrap_or_else(|| {
+            self.errors
+                .push(error(format!("`{}` is not a valid fact name", name)));
+            CoreFact::Symbol(name.to_string())
+        });
+        if let Some(f) = self.facts.get(&fact) {
+            return f.clone();
+        }
+        let f = Arc::new(fact.clone());
+        self.facts.insert(fact, f.clone());
+        self.all_facts.push(f.clone());
+        f
+    }
+    fn ground_fact(&mut self, name: &str) -> Fact {
+        let f = self.fact(name);
+        if !f.is_ground() {
+            self.errors
+                .push(error(format!("`{}` can't contain variables here", f)));
+        }
+        f
+    }
+    fn stack(&mut self, s: &StackDef) -> Stack {
+        match s {
+            StackDef::Name(name) => self.fact(name).into(),
+            StackDef::Counted { fact, count } => {
+                if *count == 0 {
+                    self.errors
+                        .push(error(format!("count of `{}` must be positive", fact)));
+                }
+                Stack::new(self.fact(fact), *count)
+            }
+        }
+    }
+    fn tag(&mut self, tags: &[Tag], s: &StackDef) -> Option<Group> {
+        let name = s.name().strip_prefix('#')?;
+        if s.count() == 0 {
+            self.errors
+                .push(error(format!("count of `{}` must be positive", s.name())));
+        }
+        let tag = tags.iter().find(|x| x.name == name)?;
+        Some(Group::tagged(tag, s.count()))
+    }
+}
+impl RuleBase {
+    fn into_engine(self) -> Result<Engine, Vec<ParseError>> {
+        let mut i = Interner::default();
+        for f in &self.facts {
+            i.fact(f);
+        }
+        let mut tags: Vec<Tag> = vec![];
+        for t in &self.tags {
+            if !parser::is_valid_name(&t.name) {
+                i.errors
+                    .push(error(format!("`{}` is not a valid tag name", t.name)));
+            } else if tags.iter().any(|x| x.name == t.name) {
+                i.errors
+                    .push(error(format!("tag `{}` is already declared", t.name)));
+            }
+            if t.members.is_empty() {
+                i.errors
+                    .push(error(format!("tag `{}` has no members", t.name)));
+            }
+            let members = t.members.iter().map(|x| i.ground_fact(x)).collect();
+            tags.push(Tag {
+                name: t.name.clone(),
+                members,
+            });
+        }
+        let mut rules = vec![];
+        let mut names = HashSet::new();
+        for (n, r) in self.rules.iter().enumerate() {
+            if r.outs.is_empty() {
+                i.errors
+                    .push(error(format!("rule #{} has no outputs", n + 1)));
+            }
+            if let Some(name) = &r.name {
+                if !parser::is_valid_name(name) {
+                    i.errors
+                        .push(error(format!("`{}` is not a valid rule name", name)));
+                } else if !names.insert(name) {
+                    i.errors
+                        .push(error(format!("rule `{}` is already declared", name)));
+                }
+            }
+            for k in r.attrs.keys() {
+                if !parser::is_valid_name(k) {
+                    i.errors
+                        .push(error(format!("`{}` is not a valid attribute name", k)));
+                }
+            }
+            let mut reqs = vec![];
+            let mut groups = vec![];
+            //"#planks" можно писать и среди обычных требований.
+            let (tagged, plain): (Vec<_>, Vec<_>) = r.reqs.iter().partition(|x| x.is_tag());
+            for x in plain {
+                reqs.push(i.stack(x));
+            }
+            for g in tagged
+                .into_iter()
+                .map(|x| GroupDef::Tag(x.clone()))
+                .chain(r.groups.clone())
+            {
+                match g {
+                    GroupDef::Tag(x) if !x.is_tag() => reqs.push(i.stack(&x)),
+                    GroupDef::Tag(x) => match i.tag(&tags, &x) {
+                        Some(g) => groups.push(g),
+                        None => i.errors.push(error(format!("unknown tag `{}`", x.name()))),
+                    },
+                    GroupDef::Options(g) => {
+                        let g: Vec<_> = g.iter().map(|x| i.stack(x)).collect();
+                        //Группа из одного варианта - обычное требование, как в тексте.
+                        match g.len() {
+                            0 => i
+                                .errors
+                                .push(error(format!("rule #{} has an empty group", n + 1))),
+                            1 => reqs.extend(g),
+                            _ => groups.push(Group::new(g)),
+                        }
+                    }
+                }
+            }
+            let catalysts: Vec<_> = r.catalysts.iter().map(|x| i.fact(x)).collect();
+            let negs: Vec<_> = r.negs.iter().map(|x| i.fact(x)).collect();
+            let outs: Vec<_> = r.outs.iter().map(|x| i.stack(x)).collect();
+            let rule = CoreRule {
+                name: r.name.clone(),
+                reqs,
+                groups,
+                catalysts,
+                negs,
+                outs,
+                attrs: r
+                    .attrs
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.to_string()))
+                    .collect(),
+            };
+            if let Some(var) = unify::unbound_vars(&rule).first() {
+                i.errors.push(error(format!(
+                    "variable `{}` of rule #{} is not bound by a requirement",
+                    var,
+                    n + 1
+                )));
+            }
+            rules.push(Arc::new(rule));
+        }
+        let starting: HashSet<_> = self.start.iter().map(|x| i.ground_fact(x)).collect();
+        let goals: Vec<Fact> = match &self.goal {
+            None => vec![],
+            Some(GoalDef::One(x)) => vec![i.ground_fact(x)],
+            Some(GoalDef::Many(v)) => v.iter().map(|x| i.ground_fact(x)).collect(),
+        };
+        if !i.errors.is_empty() {
+            return Err(i.errors);
+        }
+        let mut engine = Engine::new(i.all_facts, starting, rules);
+        engine.goals = goals;
+        engine.tags = tags;
+        engine.strata().map_err(|e| vec![error(e)])?;
+        Ok(engine)
+    }
+}
+
+fn line_column(s: &str, offset: usize) -> (usize, usize) {
+    let before = &s[..offset.min(s.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+pub fn json_error(e: serde_json::Error) -> ParseError {
+    //Позиция уже есть в line и column.
+    let message = e.to_string();
+    let message = message.rsplit_once(" at line ").map_or(&*message, |x| x.0);
+    ParseError {
+        file: None,
+        line: e.line(),
+        column: e.column(),
+        message: message.to_string(),
+    }
+}
+pub fn from_json(s: &str) -> Result<Engine, Vec<ParseError>> {
+    let base: RuleBase = serde_json::from_str(s).map_err(|e| vec![json_error(e)])?;
+    base.into_engine()
+}
+pub fn to_json(e: &Engine) -> String {
+    serde_json::to_string_pretty(&RuleBase::from(e)).expect("rule base is valid JSON")
+}
+pub fn from_toml(s: &str) -> Result<Engine, Vec<ParseError>> {
+    let base: RuleBase = toml::from_str(s).map_err(|e| {
+        let (line, column) = e.span().map_or((0, 0), |x| line_column(s, x.start));
+        vec![ParseError {
+            file: None,
+            line,
+            column,
+            message: e.message().to_string(),
+        }]
+    })?;
+    base.into_engine()
+}
+pub fn to_toml(e: &Engine) -> String {
+    toml::to_string(&RuleBase::from(e)).expect("rule base is valid TOML")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(e: Result<Engine, Vec<ParseError>>) -> Vec<(usize, usize, String)> {
+        e.unwrap_err()
+            .into_iter()
+            .map(|e| (e.line, e.column, e.message))
+            .collect()
+    }
+
+    #[test]
+    fn count_is_optional() {
+        let json = r#"{"start": ["log"], "rules": [
+            {"reqs": [{"fact": "log", "count": 2}], "outs": ["plank"]}
+        ]}"#;
+        let e = from_json(json).unwrap();
+        assert_eq!(e.rules[0].reqs[0].count, 2);
+        assert_eq!(e.rules[0].outs[0].count, 1);
+        assert_eq!(e.starting_facts.len(), 1);
+        let toml = "[[rules]]\nreqs = [{ fact = \"log\", count = 2 }]\nouts = [\"plank\"]\n";
+        assert_eq!(from_toml(toml).unwrap().rules, e.rules);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            messages(from_json(r#"{"rules": [{"reqs": ["a b"], "outs": []}]}"#)),
+            [
+                (0, 0, "rule #1 has no outputs".to_string()),
+                (0, 0, "`a b` is not a valid fact name".to_string())
+            ]
+        );
+        let toml = messages(from_toml("[[rules]]\nouts = [\"a\"]\nreqs = 1\n"));
+        assert_eq!((toml[0].0, toml[0].1), (3, 8));
+        let json = messages(from_json(
+            "{\n  \"rules\": [\n    {\"outs\": \"a\"}\n  ]\n}",
+        ));
+        assert_eq!(json[0].0, 3);
+    }
+
+    //Количества, группы, отрицания, катализаторы, теги, атрибуты, переменные и цели.
+    const SAMPLE: &str = r#"tag planks = oak_planks, birch_planks
+{log} -> 4 oak_planks
+{2 #planks} -> 4 stick @machine=crafting_table
+{coal | charcoal, stick, !wet} -> 4 torch @priority=2
+{iron_ore, @furnace} -> iron @cost="0.5"
+smelt: {cobblestone} -> furnace @note="say \"hi\" \\ twice"
+{parent(X, Y), anc(Y, Z)} -> anc(X, Z)
+{parent(X, Y)} -> anc(X, Y)
+start: log, charcoal, iron_ore, cobblestone, parent(a, b), parent(b, c)
+goal: torch, anc(a, c)
+"#;
+
+    fn assert_same(a: &Engine, b: &Engine) {
+        assert_eq!(a.all_possible_facts, b.all_possible_facts);
+        assert_eq!(a.starting_facts, b.starting_facts);
+        assert_eq!(a.rules, b.rules);
+        assert_eq!(a.goals, b.goals);
+        assert_eq!(a.tags, b.tags);
+    }
+    fn round_trip(e: &Engine) {
+        assert_same(e, &Engine::from_string(&e.to_rule_string()).unwrap());
+        assert_same(e, &from_json(&to_json(e)).unwrap());
+        assert_same(e, &from_toml(&to_toml(e)).unwrap());
+    }
+
+    #[test]
+    fn crafts_round_trip() {
+        round_trip(&Engine::from_string(include_str!("crafts.txt")).unwrap());
+    }
+
+    #[test]
+    fn sample_round_trip() {
+        let e = Engine::from_string(SAMPLE).unwrap();
+        assert_eq!(e.tags.len(), 1);
+        assert_eq!(e.goals.len(), 2);
+        assert_eq!(e.rules[4].attr("note"), Some(r#"say "hi" \ twice"#));
+        round_trip(&e);
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::fact::{Fact, Rule, Stack};
+
+//Количества имеющихся фактов, для подсчёта сколько можно скрафтить.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory {
+    counts: HashMap<Fact, u32>,
+}
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, fact: &Fact) -> u32 {
+        self.counts.get(fact).copied().unwrap_or(0)
+    }
+    pub fn set(&mut self, fact: Fact, count: u32) {
+        if count == 0 {
+            self.counts.remove(&fact);
+        } else {
+            self.counts.insert(fact, count);
+        }
+    }
+    pub fn add(&mut self, fact: &Fact, count: u32) {
+        self.set(fact.clone(), self.get(fact).saturating_add(count));
+    }
+    fn take(&mut self, fact: &Fact, count: u32) {
+        self.set(fact.clone(), self.get(fact) - count);
+    }
+    //Ничего не потрачено и ничего не стало меньше.
+    fn contains(&self, other: &Inventory) -> bool {
+        other.counts.iter().all(|(f, &c)| self.get(f) >= c)
+    }
+}
+
+pub struct Crafter<'a> {
+    producers: HashMap<&'a Fact, Vec<&'a Rule>>,
+}
+impl<'a> Crafter<'a> {
+    pub fn new(rules: &'a [Rule]) -> Self {
+        let mut producers: HashMap<&Fact, Vec<&Rule>> = HashMap::new();
+        for r in rules {
+            for out in &r.outs {
+                let v = producers.entry(&out.fact).or_default();
+                if !v.contains(&r) {
+                    v.push(r);
+                }
+            }
+        }
+        Crafter { producers }
+    }
+    //Сколько раз можно получить target, начиная с inventory. u32::MAX, если target получается из ничего.
+    //Количество ищется двоичным поиском по проверкам вида «можно ли получить сразу n штук».
+    pub fn max_craftable(&self, target: &Fact, inventory: &Inventory) -> u32 {
+        let can = |n: u32| self.produce(target, n, &mut inventory.clone(), &mut HashSet::new());
+        let mut after_one = inventory.clone();
+        if !self.produce(target, 1, &mut after_one, &mut HashSet::new()) {
+            return 0;
+        }
+        if after_one.contains(inventory) {
+            return u32::MAX;
+        }
+        //lo получить можно, hi нельзя.
+        let (mut lo, mut hi) = (1, 2);
+        while can(hi) {
+            if hi == u32::MAX {
+                return u32::MAX;
+            }
+            lo = hi;
+            hi = hi.saturating_mul(2);
+        }
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if can(mid) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+    //Забирает count штук fact из инвентаря, докрафчивая недостающее. При неудаче инвентарь не меняется.
+    fn produce(
+        &self,
+        fact: &Fact,
+        count: u32,
+        inventory: &mut Inventory,
+        visiting: &mut HashSet<Fact>,
+    ) -> bool {
+        let have = inventory.get(fact);
+        if have >= count {
+            inventory.take(fact, count);
+            return true;
+        }
+        if visiting.contains(fact) {
+            return false;
+        }
+        let need = count - have;
+        visiting.insert(fact.clone());
+        let snapshot = inventory.clone();
+        inventory.take(fact, have);
+        for r in self.producers.get(fact).into_iter().flatten() {
+            if r.negs.iter().any(|x| inventory.get(x) > 0) {
+                continue;
+            }
+            let crafts = need.div_ceil(r.produced_count(fact));
+            let before = inventory.clone();
+            let take = |req: &Stack, crafts: u32, inventory: &mut Inventory, visiting: &mut _| {
+                req.count
+                    .checked_mul(crafts)
+                    .is_some_and(|n| self.produce(&req.fact, n, inventory, visiting))
+            };
+            //Группа сначала набирается из того, что уже есть, вариант за вариантом,
+            //а остаток крафтов берётся из первого варианта, который удалось докрафтить.
+            //Катализатор нужен в одном экземпляре: сделанный однажды, он остаётся в инвентаре.
+            let ok = r
+                .reqs
+                .iter()
+                .all(|req| take(req, crafts, inventory, visiting))
+                && r.groups.iter().all(|g| {
+                    let mut left = crafts;
+                    for req in g.iter() {
+                        let k = (inventory.get(&req.fact) / req.count).min(left);
+                        inventory.take(&req.fact, k * req.count);
+                        left -= k;
+                    }
+                    left == 0 || g.iter().any(|req| take(req, left, inventory, visiting))
+                })
+                && r.catalysts.iter().all(|x| {
+                    inventory.get(x) > 0 || {
+                        let ok = self.produce(x, 1, inventory, visiting);
+                        if ok {
+                            inventory.add(x, 1);
+                        }
+                        ok
+                    }
+                });
+            if ok {
+                for out in &r.outs {
+                    inventory.add(&out.fact, crafts.saturating_mul(out.count));
+                }
+                inventory.take(fact, need);
+                visiting.remove(fact);
+                return true;
+            }
+            *inventory = before;
+        }
+        *inventory = snapshot;
+        visiting.remove(fact);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    fn fact(e: &Engine, name: &str) -> Fact {
+        let f = e.all_possible_facts.iter().find(|x| x.to_string() == name);
+        f.unwrap().clone()
+    }
+
+    #[test]
+    fn counts_intermediate_crafts() {
+        let e = Engine::from_string("{oak_wood} -> 4 oak_plank\n{2 oak_plank} -> 4 stick\n");
+        let e = e.unwrap();
+        let mut inventory = Inventory::new();
+        inventory.set(fact(&e, "oak_wood"), 3);
+        assert_eq!(e.max_craftable(&fact(&e, "stick"), &inventory), 24);
+        assert_eq!(e.max_craftable(&fact(&e, "oak_plank"), &inventory), 12);
+        inventory.set(fact(&e, "oak_plank"), 2);
+        assert_eq!(e.max_craftable(&fact(&e, "stick"), &inventory), 28);
+    }
+
+    #[test]
+    fn free_target_is_unbounded() {
+        let e = Engine::from_string("{} -> water\n{2 water} -> ice\n").unwrap();
+        let inventory = Inventory::new();
+        assert_eq!(e.max_craftable(&fact(&e, "ice"), &inventory), u32::MAX);
+    }
+
+    #[test]
+    fn byproducts_are_kept() {
+        let e = Engine::from_string("{ore} -> iron_ingot, slag\n{2 slag} -> gravel\n").unwrap();
+        let mut inventory = Inventory::new();
+        inventory.set(fact(&e, "ore"), 5);
+        assert_eq!(e.max_craftable(&fact(&e, "gravel"), &inventory), 2);
+        assert_eq!(e.max_craftable(&fact(&e, "iron_ingot"), &inventory), 5);
+    }
+
+    #[test]
+    fn group_takes_any_member() {
+        let e = Engine::from_string("{coal | charcoal, stick} -> 4 torch\n").unwrap();
+        let mut inventory = Inventory::new();
+        inventory.set(fact(&e, "coal"), 1);
+        inventory.set(fact(&e, "charcoal"), 2);
+        inventory.set(fact(&e, "stick"), 5);
+        assert_eq!(e.max_craftable(&fact(&e, "torch"), &inventory), 12);
+    }
+
+    #[test]
+    fn catalyst_is_not_consumed() {
+        let e =
+            Engine::from_string("{8 cobblestone} -> furnace\n{iron_ore, @furnace} -> iron_ingot\n");
+        let e = e.unwrap();
+        let mut inventory = Inventory::new();
+        inventory.set(fact(&e, "iron_ore"), 5);
+        assert_eq!(e.max_craftable(&fact(&e, "iron_ingot"), &inventory), 0);
+        inventory.set(fact(&e, "cobblestone"), 8);
+        assert_eq!(e.max_craftable(&fact(&e, "iron_ingot"), &inventory), 5);
+        inventory.set(fact(&e, "cobblestone"), 0);
+        inventory.set(fact(&e, "furnace"), 1);
+        assert_eq!(e.max_craftable(&fact(&e, "iron_ingot"), &inventory), 5);
+    }
+
+    #[test]
+    fn large_inventories_are_counted_in_bulk() {
+        let e = Engine::from_string(
+            "{oak_wood} -> 4 oak_plank\n{2 oak_plank} -> 4 stick\n\
+             {8 cobblestone} -> furnace\n{iron_ore, @furnace} -> iron_ingot\n\
+             {coal | charcoal, stick} -> 4 torch\n",
+        )
+        .unwrap();
+        let mut inventory = Inventory::new();
+        inventory.set(fact(&e, "oak_wood"), 1_000_000);
+        assert_eq!(e.max_craftable(&fact(&e, "stick"), &inventory), 8_000_000);
+        inventory.set(fact(&e, "iron_ore"), 3_000_000);
+        inventory.set(fact(&e, "cobblestone"), 8);
+        assert_eq!(
+            e.max_craftable(&fact(&e, "iron_ingot"), &inventory),
+            3_000_000
+        );
+        inventory.set(fact(&e, "coal"), 1_000_000);
+        inventory.set(fact(&e, "charcoal"), 2_000_000);
+        assert_eq!(e.max_craftable(&fact(&e, "torch"), &inventory), 12_000_000);
+        inventory.set(fact(&e, "oak_wood"), 250_000);
+        assert_eq!(e.max_craftable(&fact(&e, "torch"), &inventory), 8_000_000);
+    }
+}
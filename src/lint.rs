@@ -0,0 +1,247 @@
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+
+use petgraph::algo::tarjan_scc;
+
+use crate::{
+    direct_reasoning::GraphNode,
+    engine::Engine,
+    fact::{Fact, Group, Rule},
+    unify,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintWarning {
+    //Факт не получается и не используется ни одним правилом.
+    UnusedFact(Fact),
+    //Правило не срабатывает, даже если взять все факты, которые не выводятся правилами.
+    UnreachableRule(Rule),
+    //Повтор правила, объявленного раньше (порядок фактов не важен).
+    DuplicateRule(Rule),
+    SelfDependentRule(Rule),
+    Cycle { facts: Vec<Fact>, rules: Vec<Rule> },
+}
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintWarning::UnusedFact(x) => write!(f, "fact `{}` is not used by any rule", x),
+            LintWarning::UnreachableRule(r) => write!(f, "rule `{}` can never fire", r),
+            LintWarning::DuplicateRule(r) => write!(f, "rule `{}` is declared twice", r),
+            LintWarning::SelfDependentRule(r) => {
+                write!(f, "rule `{}` requires its own output", r)
+            }
+            LintWarning::Cycle { facts, .. } => {
+                let names: Vec<_> = facts.iter().map(|x| x.to_string()).collect();
+                write!(f, "cycle through {}", names.join(", "))
+            }
+        }
+    }
+}
+
+//Требования и выходы правила без учёта порядка.
+type RuleKey = (Vec<(String, u32)>, Vec<(String, u32)>);
+
+fn rule_key(r: &Rule) -> RuleKey {
+    let mut reqs: Vec<_> = r
+        .reqs
+        .iter()
+        .map(|x| (x.fact.to_string(), x.count))
+        .collect();
+    let mut outs: Vec<_> = r
+        .outs
+        .iter()
+        .map(|x| (x.fact.to_string(), x.count))
+        .collect();
+    let mut groups: Vec<_> = r
+        .groups
+        .iter()
+        .map(|g| {
+            let mut g: Vec<_> = g.iter().map(|x| x.to_string()).collect();
+            g.sort();
+            (g.join(" | "), 0)
+        })
+        .collect();
+    let mut catalysts: Vec<_> = r.catalysts.iter().map(|x| (format!("@{}", x), 0)).collect();
+    //Иначе {a, !x} совпало бы с {a, #t}, где в теге t один x.
+    let mut negs: Vec<_> = r.negs.iter().map(|x| (format!("!{}", x), 0)).collect();
+    reqs.sort();
+    groups.sort();
+    catalysts.sort();
+    negs.sort();
+    outs.sort();
+    reqs.extend(groups);
+    reqs.extend(catalysts);
+    reqs.extend(negs);
+    (reqs, outs)
+}
+
+pub fn lint(engine: &Engine) -> Vec<LintWarning> {
+    let mut warnings = vec![];
+
+    let produced: HashSet<&Fact> = engine
+        .rules
+        .iter()
+        .flat_map(|r| r.outs.iter().map(|x| &x.fact))
+        .collect();
+    let used: HashSet<&Fact> = engine
+        .rules
+        .iter()
+        .flat_map(|r| {
+            r.reqs
+                .iter()
+                .chain(r.groups.iter().flatten())
+                .map(|x| &x.fact)
+                .chain(r.catalysts.iter())
+                .chain(r.negs.iter())
+        })
+        .collect();
+    //ore(iron) используется правилом с ore(X).
+    let patterns: Vec<&Fact> = produced
+        .iter()
+        .chain(used.iter())
+        .filter(|x| !x.is_ground())
+        .copied()
+        .collect();
+    let matches = |f: &Fact| {
+        patterns
+            .iter()
+            .any(|p| unify::unify(p, f, &mut Default::default()))
+    };
+    for f in &engine.all_possible_facts {
+        if !produced.contains(f) && !used.contains(f) && !matches(f) {
+            warnings.push(LintWarning::UnusedFact(f.clone()));
+        }
+    }
+
+    let mut facts: HashSet<Fact> = engine
+        .all_possible_facts
+        .iter()
+        .filter(|x| !produced.contains(x))
+        .cloned()
+        .collect();
+    let mut unfired: Vec<&Rule> = engine.rules.iter().collect();
+    //Отрицания не учитываем: нужные факты могут и не появиться.
+    loop {
+        let before = unfired.len();
+        unfired.retain(|r| {
+            let group_ok = |g: &Group| g.iter().any(|x| facts.contains(&x.fact));
+            if r.reqs.iter().all(|x| facts.contains(&x.fact))
+                && r.catalysts.iter().all(|x| facts.contains(x))
+                && r.groups.iter().all(group_ok)
+            {
+                facts.extend(r.outs.iter().map(|x| x.fact.clone()));
+                false
+            } else {
+                true
+            }
+        });
+        if unfired.len() == before {
+            break;
+        }
+    }
+    for r in unfired {
+        warnings.push(LintWarning::UnreachableRule(r.clone()));
+    }
+
+    let mut seen = HashMap::new();
+    for r in &engine.rules {
+        if seen.insert(rule_key(r), r).is_some() {
+            warnings.push(LintWarning::DuplicateRule(r.clone()));
+        }
+        let mut reqs = r
+            .reqs
+            .iter()
+            .chain(r.groups.iter().flatten())
+            .map(|x| &x.fact)
+            .chain(r.catalysts.iter());
+        if reqs.any(|x| r.produces(x)) {
+            warnings.push(LintWarning::SelfDependentRule(r.clone()));
+        }
+    }
+
+    let order: HashMap<&Fact, usize> = engine
+        .all_possible_facts
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x, i))
+        .collect();
+    let (g, _) = engine.to_stable_graph();
+    for scc in tarjan_scc(&g) {
+        let mut facts = vec![];
+        let mut rules = vec![];
+        for i in scc {
+            match &g[i] {
+                GraphNode::Fact(f) => facts.push(f.fact.clone()),
+                GraphNode::Rule(r) => rules.push(r.rule.clone()),
+                GraphNode::Or(_) | GraphNode::Tag(_) => (),
+            }
+        }
+        //Правило, зависящее от своего выхода, уже отмечено выше.
+        if facts.len() + rules.len() < 3 {
+            continue;
+        }
+        facts.sort_by_key(|x| order.get(x).copied());
+        warnings.push(LintWarning::Cycle { facts, rules });
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(src: &str) -> Vec<String> {
+        let e = Engine::from_string(src).unwrap();
+        lint(&e).iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn unused_fact() {
+        assert_eq!(
+            warnings("lonely\n{a} -> b\n"),
+            ["fact `lonely` is not used by any rule"]
+        );
+    }
+
+    #[test]
+    fn unreachable_rules() {
+        assert_eq!(
+            warnings("{a, b} -> c\n{c} -> b\n"),
+            [
+                "rule `{a, b} -> c` can never fire",
+                "rule `{c} -> b` can never fire",
+                "cycle through b, c"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_rule() {
+        assert_eq!(
+            warnings("{a, b} -> c\n{b, a} -> c\n{a} -> 2 c\n"),
+            ["rule `{b, a} -> c` is declared twice"]
+        );
+    }
+
+    #[test]
+    fn self_dependent_rule() {
+        assert_eq!(
+            warnings("{a} -> b\n{a, b} -> 2 b\n"),
+            ["rule `{a, b} -> 2 b` requires its own output"]
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        assert_eq!(
+            warnings("{a} -> b\n{b} -> c\n{c} -> b\n"),
+            ["cycle through b, c"]
+        );
+    }
+
+    #[test]
+    fn negation_is_not_a_group() {
+        let w = warnings("tag t = x\n{a, !x} -> b\n{a, #t} -> b\n");
+        assert!(!w.iter().any(|x| x.ends_with("declared twice")), "{:?}", w);
+    }
+}
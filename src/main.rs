@@ -11,7 +11,9 @@ use std::{
     time::Duration,
 };
 
-use direct_reasoning::{DirectReasoning, FactState, GraphNode, NodeColoring, RuleState};
+use direct_reasoning::{
+    ConflictStrategy, DirectReasoning, FactState, GraphEdge, GraphNode, NodeColoring, RuleState,
+};
 use egui::{
     epaint::TextShape, Color32, ComboBox, FontFamily, FontId, Layout, Rect, RichText, Rounding,
     ScrollArea, Shape, Stroke, Vec2,
@@ -20,17 +22,40 @@ use egui_extras::{Column, TableBuilder};
 use egui_graphs::{Graph, GraphView, SettingsInteraction, SettingsStyle};
 use engine::Engine;
 use fact::{Fact, Rule};
+use inventory::Inventory;
+use lint::LintWarning;
+use parser::{FileSystem, ParseError};
 use petgraph::{stable_graph::StableGraph, visit::EdgeRef, Directed};
 use reverse_reasoning::ReverseReasoning;
+use ruletree::RuleTree;
 
+pub mod abduction;
+pub mod bench;
+pub mod derivations;
 pub mod direct_reasoning;
 pub mod engine;
 pub mod fact;
+pub mod formats;
+pub mod inventory;
+pub mod lint;
+pub mod minecraft;
+pub mod parser;
+pub mod plan;
 pub mod reverse_reasoning;
 pub mod ruletree;
+pub mod unify;
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
+    //--bench N: замер прямого вывода на сгенерированной базе из N правил вместо интерфейса.
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, n] = args.as_slice() {
+        if flag == "--bench" {
+            let n = n.parse().expect("--bench takes the number of rules");
+            print!("{}", bench::forward_chaining(n));
+            return;
+        }
+    }
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "Production system",
@@ -60,14 +85,59 @@ fn main() {
 struct MyEguiApp {
     engine: Option<Engine>,
     state: AppState,
-    g: Graph<GraphNode, (), Directed>,
+    g: Graph<GraphNode, GraphEdge, Directed>,
     coloring: NodeColoring,
     dir: Option<DirectReasoning>,
+    strategy: ConflictStrategy,
+    prune: bool,
+    saved_firings: Option<usize>,
+    //Стоимость самого дешёвого плана; Some(None) - плана нет.
+    plan_cost: Option<Option<f64>>,
+    derivations: Vec<RuleTree>,
+    derivation: usize,
+    derivation_limit: usize,
+    dedup: bool,
     rev: Option<ReverseReasoning>,
-    target_fact: Option<Fact>,
+    targets: Vec<Fact>,
     all_rules: bool,
     file_update: bool,
-    file: Arc<Mutex<Option<Vec<u8>>>>,
+    file: Arc<Mutex<Option<OpenedFile>>>,
+    parse_errors: Vec<ParseError>,
+    amounts: HashMap<Fact, u32>,
+    craftable: Vec<(Fact, u32)>,
+    warnings: Vec<LintWarning>,
+    show_warnings: bool,
+    //Текст объяснения, почему цель не выводится.
+    why_not: Option<String>,
+    //Найден ли набор недостающих начальных фактов.
+    missing_found: Option<bool>,
+}
+//Пункт меню Save: название, имя файла по умолчанию и функция записи.
+type Exporter = (&'static str, &'static str, fn(&Engine) -> String);
+//Нативно include читаются с диска, в браузере - из всех выбранных файлов.
+#[derive(Debug, Clone)]
+enum OpenedFile {
+    Text(Vec<u8>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Path(PathBuf),
+    #[cfg(target_arch = "wasm32")]
+    Uploaded(HashMap<PathBuf, String>),
+    Recipes(HashMap<PathBuf, String>),
+}
+#[cfg(not(target_arch = "wasm32"))]
+fn read_recipe_dir(dir: &std::path::Path, files: &mut HashMap<PathBuf, String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|x| x.path()) {
+        if path.is_dir() {
+            read_recipe_dir(&path, files);
+        } else if path.extension().is_some_and(|x| x == "json") {
+            if let Ok(s) = std::fs::read_to_string(&path) {
+                files.insert(path, s);
+            }
+        }
+    }
 }
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 enum AppState {
@@ -84,55 +154,84 @@ impl Default for MyEguiApp {
             coloring: Default::default(),
             g: (&StableGraph::new()).into(),
             dir: None,
-            target_fact: None,
+            strategy: ConflictStrategy::default(),
+            prune: false,
+            saved_firings: None,
+            plan_cost: None,
+            derivations: vec![],
+            derivation: 0,
+            derivation_limit: 20,
+            dedup: true,
+            targets: vec![],
             rev: None,
             all_rules: false,
             file_update: false,
             file: Arc::new(Mutex::new(None)),
+            parse_errors: vec![],
+            amounts: HashMap::new(),
+            craftable: vec![],
+            warnings: vec![],
+            show_warnings: false,
+            why_not: None,
+            missing_found: None,
         }
     }
 }
 impl MyEguiApp {
     fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let e = Engine::from_string(include_str!("crafts.txt"));
+        let e = Engine::from_string(include_str!("crafts.txt")).expect("default rules are valid");
         //println!("{:?}", e);
         // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
         // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
         // for e.g. egui::PaintCallback.
+        let mut app = Self::default();
+        app.load_engine(e);
+        app
+    }
+    //Стартовые факты и цели берутся из файла.
+    fn load_engine(&mut self, e: Engine) {
         let (g, c) = e.to_graph();
-        Self {
-            engine: Some(e),
-            g, //(&StableGraph::new()).into(),
-            coloring: c,
-            state: AppState::None,
-            dir: None,
-            rev: None,
-            target_fact: None,
-            all_rules: false,
-            file_update: false,
-            file: Arc::new(Mutex::new(None)),
+        self.g = g;
+        self.coloring = c;
+        self.targets = e.goals.clone();
+        if self.targets.is_empty() {
+            self.state = AppState::None;
         }
+        self.amounts.clear();
+        self.warnings = e.lint();
+        self.engine = Some(e);
+        self.update_state();
     }
     fn update_state(&mut self) {
+        self.plan_cost = None;
+        self.missing_found = None;
+        self.derivations.clear();
         match self.state {
             AppState::None => (),
             AppState::DirectReasoning => {
-                self.dir = Some(DirectReasoning::new(
-                    self.engine.as_ref().unwrap(),
-                    self.target_fact.as_ref().unwrap().clone(),
-                ))
+                let e = self.engine.as_ref().unwrap();
+                let mut dir =
+                    DirectReasoning::with_strategy(e, self.targets.clone(), self.strategy);
+                //Сколько срабатываний экономит отсечение, считаем прогоном обоих вариантов.
+                self.saved_firings = None;
+                if self.prune {
+                    let full = dir.remaining_firings();
+                    dir.prune(e);
+                    self.saved_firings = Some(full.saturating_sub(dir.remaining_firings()));
+                }
+                self.dir = Some(dir);
             }
             AppState::ReverseReasoning => {
-                self.rev = Some(ReverseReasoning::new(
+                self.rev = Some(ReverseReasoning::with_targets(
                     self.engine.as_ref().unwrap(),
-                    self.target_fact.as_ref().unwrap().clone(),
+                    self.targets.clone(),
                 ))
             }
         }
         match self.state {
             AppState::None => match self.engine.as_ref() {
-                Some(x) => x.recolor_node(self.target_fact.clone(), &self.coloring),
+                Some(x) => x.recolor_node(&self.targets, &self.coloring),
                 None => (),
             },
             AppState::DirectReasoning => match self.dir.as_ref() {
@@ -144,23 +243,81 @@ impl MyEguiApp {
                 None => (),
             },
         }
+        self.craftable.clear();
+        if let Some(e) = &self.engine {
+            let mut inventory = Inventory::new();
+            for f in &e.starting_facts {
+                inventory.set(f.clone(), self.amounts.get(f).copied().unwrap_or(1));
+            }
+            for t in &self.targets {
+                self.craftable
+                    .push((t.clone(), e.max_craftable(t, &inventory)));
+            }
+        }
     }
 }
 
 impl eframe::App for MyEguiApp {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if self.file.clone().lock().unwrap().is_some() {
-            let m = self.file.clone();
-            let mut l = m.lock().unwrap();
-            if let Some(t) = l.as_ref() {
-                self.engine = Some(Engine::from_string(&String::from_utf8_lossy(t)));
-                let (g, c) = self.engine.as_ref().unwrap().to_graph();
-                self.g = g;
-                self.coloring = c;
-                self.update_state();
-                *l = None;
+        let opened = self.file.lock().unwrap().take();
+        if let Some(opened) = opened {
+            let res = match opened {
+                OpenedFile::Text(t) => Engine::from_string(&String::from_utf8_lossy(&t)),
+                #[cfg(not(target_arch = "wasm32"))]
+                OpenedFile::Path(p) => Engine::from_file(&p, &FileSystem),
+                #[cfg(target_arch = "wasm32")]
+                OpenedFile::Uploaded(files) => {
+                    let root = parser::root_file(&files).unwrap_or_default();
+                    Engine::from_file(&root, &files)
+                }
+                OpenedFile::Recipes(files) => Engine::from_recipes(&files),
+            };
+            match res {
+                Ok(e) => {
+                    self.parse_errors.clear();
+                    self.load_engine(e);
+                }
+                Err(errors) => self.parse_errors = errors,
+            }
+        }
+        if !self.parse_errors.is_empty() {
+            let mut open = true;
+            egui::Window::new("Parse errors")
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ScrollArea::vertical().show(ui, |ui| {
+                        for e in &self.parse_errors {
+                            ui.label(RichText::new(format!("{}", e)).color(Color32::LIGHT_RED));
+                        }
+                    });
+                });
+            if !open {
+                self.parse_errors.clear();
             }
         }
+        egui::Window::new("Rule base warnings")
+            .open(&mut self.show_warnings)
+            .show(ctx, |ui| {
+                if self.warnings.is_empty() {
+                    ui.label("No warnings");
+                }
+                ScrollArea::vertical().show(ui, |ui| {
+                    for w in &self.warnings {
+                        ui.label(format!("{}", w));
+                    }
+                });
+            });
+        let mut open = self.why_not.is_some();
+        egui::Window::new("Why not?")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ScrollArea::both().show(ui, |ui| {
+                    ui.monospace(self.why_not.as_deref().unwrap_or_default());
+                });
+            });
+        if !open {
+            self.why_not = None;
+        }
         egui::TopBottomPanel::top("Controls")
             .resizable(false)
             .show(ctx, |ui| {
@@ -168,7 +325,7 @@ impl eframe::App for MyEguiApp {
                     egui::widgets::global_dark_light_mode_switch(ui);
                     if ui.button("Open File").clicked() {
                         //let mut file : Arc<Mutex<Option<_>>> = Arc::new(Mutex::new(None));
-                        let mut v: Arc<Mutex<Option<Vec<u8>>>> = self.file.clone();
+                        let v: Arc<Mutex<Option<OpenedFile>>> = self.file.clone();
                         //let (r, w) = channel::<Vec<u8>>();
                         //rfd::FileDialog::new().pick_file();
                         //r.send(t);
@@ -176,14 +333,21 @@ impl eframe::App for MyEguiApp {
                         {
                             let v = v.clone();
                             wasm_bindgen_futures::spawn_local(async move {
-                                let task = rfd::AsyncFileDialog::new().pick_file();
+                                let task = rfd::AsyncFileDialog::new().pick_files();
 
                                 //let f = task.await;
                                 if let Some(t) = task.await {
-                                    log::info!("File uploaded!");
-                                    let q = t.read().await;
-                                    log::info!("File readed!");
-                                    *v.lock().unwrap() = Some(q);
+                                    log::info!("Files uploaded!");
+                                    let mut files = HashMap::new();
+                                    for f in t {
+                                        let q = f.read().await;
+                                        files.insert(
+                                            PathBuf::from(f.file_name()),
+                                            String::from_utf8_lossy(&q).into_owned(),
+                                        );
+                                    }
+                                    log::info!("Files readed!");
+                                    *v.lock().unwrap() = Some(OpenedFile::Uploaded(files));
                                 }
                             });
                         }
@@ -194,13 +358,80 @@ impl eframe::App for MyEguiApp {
                                 let task = rfd::AsyncFileDialog::new().pick_file();
                                 let f = task.await;
                                 if let Some(t) = f {
-                                    *v.lock().unwrap() = Some(t.read().await);
+                                    *v.lock().unwrap() =
+                                        Some(OpenedFile::Path(t.path().to_path_buf()));
                                 }
                             });
                         }
                     }
+                    if ui.button("Import recipes").clicked() {
+                        let v = self.file.clone();
+                        #[cfg(target_arch = "wasm32")]
+                        wasm_bindgen_futures::spawn_local(async move {
+                            let task = rfd::AsyncFileDialog::new()
+                                .add_filter("Recipes", &["json"])
+                                .pick_files();
+                            if let Some(t) = task.await {
+                                let mut files = HashMap::new();
+                                for f in t {
+                                    let q = f.read().await;
+                                    files.insert(
+                                        PathBuf::from(f.file_name()),
+                                        String::from_utf8_lossy(&q).into_owned(),
+                                    );
+                                }
+                                *v.lock().unwrap() = Some(OpenedFile::Recipes(files));
+                            }
+                        });
+                        #[cfg(not(target_arch = "wasm32"))]
+                        futures::executor::block_on(async move {
+                            let task = rfd::AsyncFileDialog::new().pick_folder();
+                            if let Some(t) = task.await {
+                                let mut files = HashMap::new();
+                                read_recipe_dir(t.path(), &mut files);
+                                *v.lock().unwrap() = Some(OpenedFile::Recipes(files));
+                            }
+                        });
+                    }
+                    ui.menu_button("Save", |ui| {
+                        let formats: [Exporter; 3] = [
+                            ("Rules", "rules.txt", Engine::to_rule_string),
+                            ("JSON", "rules.json", Engine::to_json),
+                            ("TOML", "rules.toml", Engine::to_toml),
+                        ];
+                        for (label, file_name, to_string) in formats {
+                            if !ui.button(label).clicked() {
+                                continue;
+                            }
+                            ui.close_menu();
+                            if let Some(e) = &self.engine {
+                                let text = to_string(e);
+                                let save = async move {
+                                    let task = rfd::AsyncFileDialog::new()
+                                        .set_file_name(file_name)
+                                        .save_file();
+                                    if let Some(t) = task.await {
+                                        if let Err(e) = t.write(text.as_bytes()).await {
+                                            log::error!("Can't save file: {}", e);
+                                        }
+                                    }
+                                };
+                                #[cfg(target_arch = "wasm32")]
+                                wasm_bindgen_futures::spawn_local(save);
+                                #[cfg(not(target_arch = "wasm32"))]
+                                futures::executor::block_on(save);
+                            }
+                        }
+                    });
+                    if ui
+                        .button(format!("Warnings ({})", self.warnings.len()))
+                        .clicked()
+                    {
+                        self.show_warnings = !self.show_warnings;
+                    }
                     if ui.button("Load default").clicked() {
-                        *self.file.lock().unwrap() = Some(include_bytes!("crafts.txt").to_vec())
+                        *self.file.lock().unwrap() =
+                            Some(OpenedFile::Text(include_bytes!("crafts.txt").to_vec()))
                     }
                     // ComboBox::from_label("").
                     //     selected_text(format!("{}", self.target_fact.as_ref().map(|x|format!("{}",x)).unwrap_or_else(||"None".to_string()))).show_ui(ui, |ui|{
@@ -224,11 +455,45 @@ impl eframe::App for MyEguiApp {
                                 "Reverse",
                             );
                         });
-                    if self.target_fact.is_none() {
+                    if self.targets.is_empty() {
                         self.state = AppState::None;
                     } else if prev != self.state {
                         self.update_state()
                     }
+                    if self.state == AppState::DirectReasoning {
+                        let prev = self.strategy;
+                        ComboBox::from_label("Conflict strategy")
+                            .selected_text(self.strategy.name())
+                            .show_ui(ui, |ui| {
+                                let seed = match self.strategy {
+                                    ConflictStrategy::Random(seed) => seed,
+                                    _ => 0,
+                                };
+                                for s in [
+                                    ConflictStrategy::DeclarationOrder,
+                                    ConflictStrategy::FewestRequirements,
+                                    ConflictStrategy::MostRequirements,
+                                    ConflictStrategy::Recency,
+                                    ConflictStrategy::Priority,
+                                    ConflictStrategy::Random(seed),
+                                ] {
+                                    ui.selectable_value(&mut self.strategy, s, s.name());
+                                }
+                            });
+                        if let ConflictStrategy::Random(seed) = &mut self.strategy {
+                            ui.add(egui::DragValue::new(seed).prefix("seed: "));
+                        }
+                        if ui
+                            .checkbox(&mut self.prune, "Only relevant rules")
+                            .changed()
+                            || prev != self.strategy
+                        {
+                            self.update_state()
+                        }
+                        if let Some(n) = self.saved_firings {
+                            ui.label(format!("Saved firings: {}", n));
+                        }
+                    }
                     if ui.button("Iterate to find").clicked() {
                         match self.state {
                             AppState::None => (),
@@ -254,6 +519,87 @@ impl eframe::App for MyEguiApp {
                             }
                         }
                     }
+                    //Отмечает в Starting самый маленький набор недостающих фактов,
+                    //для нескольких целей - по очереди для каждой.
+                    if let (Some(e), false) = (&mut self.engine, self.targets.is_empty()) {
+                        if ui.button("Add missing facts").clicked() {
+                            let mut found = true;
+                            for t in &self.targets {
+                                match e.missing_starting_facts(t, 1, true).into_iter().next() {
+                                    Some(set) => e.starting_facts.extend(set),
+                                    None => found = false,
+                                }
+                            }
+                            self.update_state();
+                            self.missing_found = Some(found);
+                        }
+                        if self.missing_found == Some(false) {
+                            ui.label("No facts help");
+                        }
+                    }
+                    if let (AppState::ReverseReasoning, Some(rev)) = (&self.state, &self.rev) {
+                        if ui.button("Why not?").clicked() {
+                            let explained = rev.explain();
+                            self.why_not = Some(if explained.is_empty() {
+                                "All targets are derivable".to_string()
+                            } else {
+                                explained.iter().map(|x| x.to_string()).collect()
+                            });
+                        }
+                    }
+                    //План и выводы ищутся для первой цели.
+                    if let (Some(e), Some(t)) = (&self.engine, self.targets.first()) {
+                        if ui.button("Cheapest plan").clicked() {
+                            let plan = e.cheapest_plan(t);
+                            if let Some(p) = &plan {
+                                e.recolor_tree(&p.tree, &self.coloring);
+                            }
+                            self.plan_cost = Some(plan.map(|p| p.cost));
+                        }
+                        match self.plan_cost {
+                            Some(Some(c)) => _ = ui.label(format!("Plan cost: {}", c)),
+                            Some(None) => _ = ui.label("No plan"),
+                            None => (),
+                        }
+                        ui.add(
+                            egui::DragValue::new(&mut self.derivation_limit)
+                                .clamp_range(1..=1000)
+                                .prefix("limit: "),
+                        );
+                        ui.checkbox(&mut self.dedup, "Dedup");
+                        if ui.button("All derivations").clicked() {
+                            self.derivations = e
+                                .derivations(t.clone(), self.derivation_limit, self.dedup)
+                                .collect();
+                            self.derivation = 0;
+                            if let Some(d) = self.derivations.first() {
+                                e.recolor_tree(d, &self.coloring);
+                            }
+                        }
+                        //Выбранный вывод подсвечивается на графе.
+                        if !self.derivations.is_empty() {
+                            let prev = self.derivation;
+                            ComboBox::from_label("Derivation")
+                                .selected_text(format!(
+                                    "{} of {}",
+                                    self.derivation + 1,
+                                    self.derivations.len()
+                                ))
+                                .show_ui(ui, |ui| {
+                                    for (i, d) in self.derivations.iter().enumerate() {
+                                        ui.selectable_value(
+                                            &mut self.derivation,
+                                            i,
+                                            format!("{}: {} rules", i + 1, d.rules().len()),
+                                        )
+                                        .on_hover_text(d.to_string());
+                                    }
+                                });
+                            if prev != self.derivation {
+                                e.recolor_tree(&self.derivations[self.derivation], &self.coloring);
+                            }
+                        }
+                    }
                 })
             });
         let mut update_state = false;
@@ -264,7 +610,8 @@ impl eframe::App for MyEguiApp {
                     .resizable(false)
                     .column(Column::exact(25.0))
                     .column(Column::auto().at_least(50.0))
-                    .column(Column::exact(25.0));
+                    .column(Column::exact(25.0))
+                    .column(Column::exact(50.0));
                 table
                     .header(20.0, |mut header| {
                         header.col(|ui| {
@@ -276,6 +623,9 @@ impl eframe::App for MyEguiApp {
                         header.col(|ui| {
                             ui.strong("Target");
                         });
+                        header.col(|ui| {
+                            ui.strong("Amount");
+                        });
                     })
                     .body(|mut body| {
                         if let Some(e) = &mut self.engine {
@@ -287,8 +637,13 @@ impl eframe::App for MyEguiApp {
                                 //     e.rules[row_index].reqs.iter().map(|x|format!("{}", x)).reduce(|x, y|x + ", " + &y).unwrap_or_default()
                                 // ); });
                                 // row.col(|ui|{ ui.label(format!("{}", e.rules[row_index].out)); });
+                                //Факты с переменными - образцы из правил, их нельзя выбрать.
+                                let ground = f.is_ground();
                                 row.col(|ui| {
-                                    ui.checkbox(&mut start, "");
+                                    ui.add_enabled(
+                                        ground,
+                                        egui::Checkbox::without_text(&mut start),
+                                    );
                                 });
                                 if old_start != start {
                                     if start {
@@ -301,13 +656,32 @@ impl eframe::App for MyEguiApp {
                                 row.col(|ui| {
                                     ui.label(format!("{}", f));
                                 });
-                                let t = self.target_fact.clone();
+                                let mut target = self.targets.contains(&f);
                                 row.col(|ui| {
-                                    ui.radio_value(&mut self.target_fact, Some(f.clone()), "");
+                                    if ground {
+                                        ui.checkbox(&mut target, "");
+                                    }
                                 });
-                                if t != self.target_fact {
+                                if target != self.targets.contains(&f) {
+                                    if target {
+                                        self.targets.push(f.clone());
+                                    } else {
+                                        self.targets.retain(|x| x != &f);
+                                    }
                                     update_state = true;
                                 }
+                                row.col(|ui| {
+                                    if start {
+                                        let amount = self.amounts.entry(f.clone()).or_insert(1);
+                                        let changed = ui
+                                            .add(
+                                                egui::DragValue::new(amount)
+                                                    .clamp_range(1..=u32::MAX),
+                                            )
+                                            .changed();
+                                        update_state |= changed;
+                                    }
+                                });
                             })
                         }
                     });
@@ -331,6 +705,13 @@ impl eframe::App for MyEguiApp {
                     ui.label(RichText::new("Dead end while searching path to target fact(only in reversive production system)").color(Color32::DARK_RED).background_color(Color32::LIGHT_GRAY));
                 });
                 ui.vertical(|ui| {
+                    for (t, n) in &self.craftable {
+                        if *n == u32::MAX {
+                            ui.label(format!("{} can be crafted without limit", t));
+                        } else {
+                            ui.label(format!("{} can be crafted {} times from starting amounts", t, n));
+                        }
+                    }
                     ui.checkbox(&mut self.all_rules, "Show all rules:");
                     if self.all_rules {
                         for i in &self.engine.as_ref().unwrap().rules {
@@ -341,13 +722,13 @@ impl eframe::App for MyEguiApp {
                         match self.state {
                             AppState::None => (),
                             AppState::DirectReasoning => {
-                                for i in self.dir.as_ref().unwrap().all_rules.iter().filter(|&x|!self.dir.as_ref().unwrap().unused_rules.contains(x)) {
-                                    ui.label(format!("{}", i));
+                                for i in &self.dir.as_ref().unwrap().used_rules {
+                                    ui.label(i.label()).on_hover_text(i.to_string());
                                 }
                             },
                             AppState::ReverseReasoning => {
                                 for i in self.rev.as_ref().unwrap().get_applied_rules() {
-                                    ui.label(format!("{}", i));
+                                    ui.label(i.label()).on_hover_text(i.to_string());
                                 }
                             },
                         }
@@ -362,6 +743,43 @@ impl eframe::App for MyEguiApp {
             let mut gw = GraphView::new(&mut self.g)
                 .with_interactions(interaction_settings)
                 .with_styles(style_settings)
+                //Рёбра от катализаторов рисуются пунктиром.
+                .with_custom_edge_draw(|ctx: &egui::Context, (start, end), edges, state, l| {
+                    let (catalysts, other): (Vec<_>, Vec<_>) = edges
+                        .into_iter()
+                        .partition(|e| e.data() == Some(&GraphEdge::Catalyst));
+                    if !other.is_empty() {
+                        egui_graphs::default_edges_draw(ctx, (start, end), other, state, l);
+                    }
+                    let (Some(e), Some(a), Some(b)) =
+                        (catalysts.first(), state.g.node(start), state.g.node(end))
+                    else {
+                        return;
+                    };
+                    let from = a.screen_location(state.meta).to_pos2();
+                    let to = b.screen_location(state.meta).to_pos2();
+                    let dir = (to - from).normalized();
+                    let from = from + dir * a.screen_radius(state.meta, state.style);
+                    let to = to - dir * b.screen_radius(state.meta, state.style);
+                    let tip = e.tip_size() * state.meta.zoom;
+                    let color = e.color(ctx);
+                    let stroke = Stroke::new(e.width() * state.meta.zoom, color);
+                    l.add(Shape::dashed_line(
+                        &[from, to - dir * tip],
+                        stroke,
+                        6.0,
+                        4.0,
+                    ));
+                    l.add(Shape::convex_polygon(
+                        vec![
+                            to,
+                            to - dir * tip + dir.rot90() * tip * 0.5,
+                            to - dir * tip - dir.rot90() * tip * 0.5,
+                        ],
+                        color,
+                        Stroke::NONE,
+                    ));
+                })
                 .with_custom_node_draw(|ctx: &egui::Context, n, state, l| {
                     let node_center_loc = n.screen_location(state.meta).to_pos2();
                     let rad = n.screen_radius(state.meta, state.style);
@@ -372,7 +790,7 @@ impl eframe::App for MyEguiApp {
                     //let node_color =  if self.engine.unwrap().starting_facts.connect(n.data().unwrap()) {Color32::GREEN} else {Color32::GRAY};
                     //let node_color = Color32::GRAY;
                     let shape_color = match n.data().unwrap() {
-                        GraphNode::Rule(r) => r
+                        GraphNode::Rule(r) | GraphNode::Or(r) => r
                             .state
                             .read()
                             .unwrap()
@@ -400,19 +818,30 @@ impl eframe::App for MyEguiApp {
                                 FactState::TargetNotPossible => Color32::LIGHT_RED,
                             })
                             .unwrap_or(Color32::GRAY),
+                        GraphNode::Tag(_) => Color32::from_rgb(150, 110, 200),
                     };
                     let shape_rect = Shape::rect_filled(rect, Rounding::default(), shape_color);
                     let shape_circle = Shape::circle_filled(node_center_loc, rad, shape_color);
                     match n.data().unwrap() {
                         GraphNode::Rule(_) => l.add(shape_rect),
                         GraphNode::Fact(_) => l.add(shape_circle),
+                        GraphNode::Or(_) => l.add(Shape::circle_filled(
+                            node_center_loc,
+                            rad * 0.5,
+                            shape_color,
+                        )),
+                        GraphNode::Tag(_) => l.add(Shape::rect_filled(
+                            rect,
+                            Rounding::same(rad * 0.5),
+                            shape_color,
+                        )),
                     }
 
                     let color = ctx.style().visuals.text_color();
                     let galley = match n.data().unwrap() {
                         GraphNode::Rule(r) => ctx.fonts(|f| {
                             f.layout_no_wrap(
-                                format!("{:}", r.rule),
+                                r.rule.label(),
                                 FontId::new(rad * 1.5, FontFamily::Monospace),
                                 color,
                             )
@@ -424,6 +853,20 @@ impl eframe::App for MyEguiApp {
                                 color,
                             )
                         }),
+                        GraphNode::Or(_) => ctx.fonts(|f| {
+                            f.layout_no_wrap(
+                                "or".to_string(),
+                                FontId::new(rad * 1.5, FontFamily::Monospace),
+                                color,
+                            )
+                        }),
+                        GraphNode::Tag(name) => ctx.fonts(|f| {
+                            f.layout_no_wrap(
+                                format!("#{}", name),
+                                FontId::new(rad * 1.5, FontFamily::Monospace),
+                                color,
+                            )
+                        }),
                     };
                     // we need to offset label by half its size to place it in the center of the rect
                     let offset = Vec2::new(
@@ -0,0 +1,564 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde_json::Value;
+
+use crate::{
+    engine::Engine,
+    fact::{CoreFact, CoreRule, Fact, Group, Rule, Stack, Tag},
+    formats,
+    parser::{self, ParseError},
+};
+
+//Импорт рецептов из data pack: crafting_shaped, crafting_shapeless и smelting.
+//Остальные типы рецептов и файлы без типа пропускаются.
+//Теги предметов берутся из tags/items (или tags/item); теги без файла остаются фактами tag_<name>.
+
+//minecraft:oak_planks -> oak_planks, mod:gear -> mod_gear, #minecraft:planks -> tag_planks
+fn fact_name(id: &str) -> String {
+    let (tag, id) = match id.strip_prefix('#') {
+        Some(id) => ("tag_", id),
+        None => ("", id),
+    };
+    let id = id.strip_prefix("minecraft:").unwrap_or(id);
+    let id: String = id
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}{}", tag, id)
+}
+
+//Ингредиент: список взаимозаменяемых предметов.
+fn ingredient(v: &Value) -> Result<Vec<String>, String> {
+    match v {
+        Value::String(s) => Ok(vec![fact_name(s)]),
+        Value::Object(o) => {
+            if let Some(Value::String(s)) = o.get("item") {
+                Ok(vec![fact_name(s)])
+            } else if let Some(Value::String(s)) = o.get("tag") {
+                Ok(vec![fact_name(&format!("#{}", s))])
+            } else {
+                Err("ingredient must have `item` or `tag`".to_string())
+            }
+        }
+        Value::Array(a) => {
+            let mut res = vec![];
+            for i in a {
+                for f in ingredient(i)? {
+                    if !res.contains(&f) {
+                        res.push(f);
+                    }
+                }
+            }
+            if res.is_empty() {
+                Err("empty ingredient".to_string())
+            } else {
+                Ok(res)
+            }
+        }
+        _ => Err(format!("unexpected ingredient `{}`", v)),
+    }
+}
+
+fn result(v: Option<&Value>) -> Result<(String, u32), String> {
+    match v {
+        Some(Value::String(s)) => Ok((fact_name(s), 1)),
+        Some(Value::Object(o)) => {
+            let id = match (o.get("item"), o.get("id")) {
+                (Some(Value::String(s)), _) | (_, Some(Value::String(s))) => s,
+                _ => return Err("result must have `item` or `id`".to_string()),
+            };
+            let count = match o.get("count") {
+                None => 1,
+                Some(c) => c
+                    .as_u64()
+                    .and_then(|c| u32::try_from(c).ok())
+                    .filter(|&c| c > 0)
+                    .ok_or_else(|| format!("invalid count `{}`", c))?,
+            };
+            Ok((fact_name(id), count))
+        }
+        _ => Err("missing `result`".to_string()),
+    }
+}
+
+//Имя тега по пути data/<ns>/tags/items/<name>.json, None для остальных файлов.
+//Строится так же, как факт для #<ns>:<name>, без приставки tag_.
+fn tag_name(path: &Path) -> Option<String> {
+    let parts: Vec<_> = path
+        .with_extension("")
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let i = parts
+        .windows(2)
+        .position(|w| w[0] == "tags" && (w[1] == "items" || w[1] == "item"))?;
+    let rest = &parts[i + 2..];
+    if rest.is_empty() {
+        return None;
+    }
+    let ns = i.checked_sub(1).map_or("minecraft", |j| parts[j].as_str());
+    let name = fact_name(&format!("#{}:{}", ns, rest.join("/")));
+    name.strip_prefix("tag_").map(str::to_string)
+}
+
+//Значения тега как есть: minecraft:oak_planks или #minecraft:logs.
+fn tag_values(v: &Value) -> Result<Vec<String>, String> {
+    let Some(Value::Array(values)) = v.get("values") else {
+        return Err("missing `values`".to_string());
+    };
+    values
+        .iter()
+        .map(|x| match x {
+            Value::String(s) => Ok(s.clone()),
+            Value::Object(o) => match o.get("id") {
+                Some(Value::String(s)) => Ok(s.clone()),
+                _ => Err("tag value must have `id`".to_string()),
+            },
+            _ => Err(format!("unexpected tag value `{}`", x)),
+        })
+        .collect()
+}
+
+//Одинаковые ингредиенты складываются в одно требование с количеством.
+struct Recipe {
+    reqs: Vec<(Vec<String>, u32)>,
+    out: (String, u32),
+    machine: Option<&'static str>,
+}
+
+fn recipe(v: &Value) -> Result<Option<Recipe>, String> {
+    let Some(Value::String(t)) = v.get("type") else {
+        return Ok(None);
+    };
+    let t = t.strip_prefix("minecraft:").unwrap_or(t);
+    let ingredients: Vec<Vec<String>> = match t {
+        "crafting_shaped" => {
+            let key = match v.get("key") {
+                Some(Value::Object(o)) => o,
+                _ => return Err("missing `key`".to_string()),
+            };
+            let pattern = match v.get("pattern") {
+                Some(Value::Array(a)) => a,
+                _ => return Err("missing `pattern`".to_string()),
+            };
+            let mut res = vec![];
+            for row in pattern {
+                let row = row.as_str().ok_or("pattern rows must be strings")?;
+                for c in row.chars().filter(|&c| c != ' ') {
+                    let i = key
+                        .get(&c.to_string())
+                        .ok_or_else(|| format!("`{}` is not in `key`", c))?;
+                    res.push(ingredient(i)?);
+                }
+            }
+            res
+        }
+        "crafting_shapeless" => match v.get("ingredients") {
+            Some(Value::Array(a)) => a.iter().map(ingredient).collect::<Result<_, _>>()?,
+            _ => return Err("missing `ingredients`".to_string()),
+        },
+        "smelting" => match v.get("ingredient") {
+            Some(i) => vec![ingredient(i)?],
+            None => return Err("missing `ingredient`".to_string()),
+        },
+        _ => return Ok(None),
+    };
+    let mut reqs: Vec<(Vec<String>, u32)> = vec![];
+    for i in ingredients {
+        match reqs.iter_mut().find(|x| x.0 == i) {
+            Some(x) => x.1 += 1,
+            None => reqs.push((i, 1)),
+        }
+    }
+    Ok(Some(Recipe {
+        reqs,
+        out: result(v.get("result"))?,
+        machine: (t == "smelting").then_some("furnace"),
+    }))
+}
+
+#[derive(Default)]
+struct Importer {
+    facts: HashMap<String, Fact>,
+    all_facts: Vec<Fact>,
+    rules: Vec<Rule>,
+    rule_names: HashSet<String>,
+    //Все теги из файлов; в базу попадают только те, что встречаются в рецептах.
+    tag_files: HashMap<String, Vec<String>>,
+    tags: Vec<Tag>,
+}
+impl Importer {
+    fn fact(&mut self, name: &str) -> Fact {
+        if let Some(f) = self.facts.get(name) {
+            return f.clone();
+        }
+        let f = CoreFact::new(name);
+        self.facts.insert(name.to_string(), f.clone());
+        self.all_facts.push(f.clone());
+        f
+    }
+    //Члены тега с раскрытием вложенных тегов.
+    fn tag_members(&self, name: &str, seen: &mut Vec<String>, res: &mut Vec<String>) {
+        seen.push(name.to_string());
+        for v in &self.tag_files[name] {
+            let fact = fact_name(v);
+            match fact.strip_prefix("tag_") {
+                Some(tag) if v.starts_with('#') && self.tag_files.contains_key(tag) => {
+                    if !seen.iter().any(|x| x == tag) {
+                        self.tag_members(tag, seen, res);
+                    }
+                }
+                _ => {
+                    if !res.contains(&fact) {
+                        res.push(fact);
+                    }
+                }
+            }
+        }
+    }
+    //Тег для факта tag_<name>, если для него есть файл.
+    fn tag(&mut self, fact: &str) -> Option<Tag> {
+        let name = fact.strip_prefix("tag_")?;
+        if let Some(tag) = self.tags.iter().find(|x| x.name == name) {
+            return Some(tag.clone());
+        }
+        if !self.tag_files.contains_key(name) {
+            return None;
+        }
+        let mut members = vec![];
+        self.tag_members(name, &mut vec![], &mut members);
+        let tag = Tag {
+            name: name.to_string(),
+            members: members.iter().map(|x| self.fact(x)).collect(),
+        };
+        self.tags.push(tag.clone());
+        Some(tag)
+    }
+    //Имя правила по имени файла; повторы получают номер.
+    //Имя из одних цифр (1.json) лексер прочитал бы как число, такое получает приставку.
+    fn rule_name(&mut self, base: &str) -> String {
+        let base = if parser::is_valid_name(base) {
+            base.to_string()
+        } else {
+            format!("recipe_{}", base)
+        };
+        let mut name = base.clone();
+        let mut n = 1;
+        while !self.rule_names.insert(name.clone()) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+        name
+    }
+    //Ингредиент с вариантами становится группой {a | b}, ингредиент-тег - требованием #tag.
+    //Теги среди нескольких вариантов раскрываются в своих членов.
+    fn add(&mut self, recipe: &Recipe, name: &str) {
+        let mut reqs = vec![];
+        let mut groups = vec![];
+        for (alternatives, count) in &recipe.reqs {
+            if let [single] = alternatives.as_slice() {
+                if let Some(tag) = self.tag(single) {
+                    groups.push(Group::tagged(&tag, *count));
+                    continue;
+                }
+            }
+            let mut options: Vec<Stack> = vec![];
+            for x in alternatives {
+                let facts = match self.tag(x) {
+                    Some(tag) => tag.members,
+                    None => vec![self.fact(x)],
+                };
+                for f in facts {
+                    if !options.iter().any(|x| x.fact == f) {
+                        options.push(Stack::new(f, *count));
+                    }
+                }
+            }
+            if options.len() == 1 {
+                reqs.append(&mut options);
+            } else {
+                groups.push(Group::new(options));
+            }
+        }
+        let out = Stack::new(self.fact(&recipe.out.0), recipe.out.1);
+        let attrs: BTreeMap<_, _> = recipe
+            .machine
+            .map(|m| ("machine".to_string(), m.to_string()))
+            .into_iter()
+            .collect();
+        let name = self.rule_name(name);
+        self.rules.push(Arc::new(CoreRule {
+            name: Some(name),
+            reqs,
+            groups,
+            catalysts: vec![],
+            negs: vec![],
+            outs: vec![out],
+            attrs,
+        }));
+    }
+}
+
+pub fn import_recipes(files: &HashMap<PathBuf, String>) -> Result<Engine, Vec<ParseError>> {
+    let mut paths: Vec<_> = files
+        .keys()
+        .filter(|x| x.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+    let mut importer = Importer::default();
+    let mut errors = vec![];
+    let (tag_paths, paths): (Vec<_>, Vec<_>) =
+        paths.into_iter().partition(|x| tag_name(x).is_some());
+    for path in tag_paths {
+        let res = serde_json::from_str::<Value>(&files[path])
+            .map_err(formats::json_error)
+            .and_then(|v| {
+                tag_values(&v).map_err(|message| ParseError {
+                    file: None,
+                    line: 0,
+                    column: 0,
+                    message,
+                })
+            });
+        match res {
+            Ok(values) => {
+                let name = tag_name(path).unwrap_or_default();
+                importer.tag_files.entry(name).or_default().extend(values);
+            }
+            Err(mut e) => {
+                e.file = Some(path.clone());
+                errors.push(e);
+            }
+        }
+    }
+    for path in paths {
+        let res = serde_json::from_str::<Value>(&files[path])
+            .map_err(formats::json_error)
+            .and_then(|v| {
+                recipe(&v).map_err(|message| ParseError {
+                    file: None,
+                    line: 0,
+                    column: 0,
+                    message,
+                })
+            });
+        match res {
+            Ok(Some(r)) => {
+                let stem = path
+                    .file_stem()
+                    .map_or("recipe".into(), |x| x.to_string_lossy());
+                importer.add(&r, &fact_name(&stem))
+            }
+            Ok(None) => (),
+            Err(mut e) => {
+                e.file = Some(path.clone());
+                errors.push(e);
+            }
+        }
+    }
+    if errors.is_empty() {
+        let mut engine = Engine::new(importer.all_facts, HashSet::new(), importer.rules);
+        engine.tags = importer.tags;
+        Ok(engine)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fact::Stack;
+
+    fn stacks<'a>(v: impl Iterator<Item = &'a Stack>) -> Vec<(String, u32)> {
+        v.map(|x| (x.fact.to_string(), x.count)).collect()
+    }
+
+    #[test]
+    fn imports_recipe_types() {
+        let files: HashMap<PathBuf, String> = [
+            (
+                "recipes/stick.json",
+                r##"{"type": "minecraft:crafting_shaped", "pattern": ["#", "#"],
+                    "key": {"#": {"item": "minecraft:oak_planks"}},
+                    "result": {"item": "minecraft:stick", "count": 4}}"##,
+            ),
+            (
+                "recipes/torch.json",
+                r#"{"type": "minecraft:crafting_shapeless",
+                    "ingredients": [{"item": "minecraft:coal"}, {"item": "minecraft:stick"}],
+                    "result": {"item": "minecraft:torch", "count": 4}}"#,
+            ),
+            (
+                "recipes/iron_ingot.json",
+                r#"{"type": "minecraft:smelting", "ingredient": {"item": "minecraft:iron_ore"},
+                    "result": "minecraft:iron_ingot"}"#,
+            ),
+            (
+                "recipes/banner.json",
+                r#"{"type": "minecraft:crafting_special_bannerduplicate"}"#,
+            ),
+            ("pack.mcmeta", "{}"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.into(), v.to_string()))
+        .collect();
+        let e = import_recipes(&files).unwrap();
+        assert_eq!(e.rules.len(), 3);
+        let one = |s: &str| (s.to_string(), 1);
+        assert_eq!(stacks(e.rules[0].reqs.iter()), [one("iron_ore")]);
+        assert_eq!(stacks(e.rules[0].outs.iter()), [one("iron_ingot")]);
+        assert_eq!(
+            stacks(e.rules[1].reqs.iter()),
+            [("oak_planks".to_string(), 2)]
+        );
+        assert_eq!(stacks(e.rules[1].outs.iter()), [("stick".to_string(), 4)]);
+        assert_eq!(stacks(e.rules[2].reqs.iter()), [one("coal"), one("stick")]);
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        let files: HashMap<PathBuf, String> = [(
+            "recipes/bad.json".into(),
+            r#"{"type": "minecraft:crafting_shaped"}"#.to_string(),
+        )]
+        .into_iter()
+        .collect();
+        let e = import_recipes(&files).unwrap_err();
+        assert_eq!(e[0].file, Some("recipes/bad.json".into()));
+        assert_eq!(e[0].message, "missing `key`");
+    }
+
+    #[test]
+    fn rules_are_named_after_files() {
+        let stick = r##"{"type": "minecraft:crafting_shaped", "pattern": ["#", "#"],
+            "key": {"#": {"item": "minecraft:oak_planks"}}, "result": {"item": "minecraft:stick"}}"##;
+        let files: HashMap<PathBuf, String> = [
+            ("a/recipes/stick.json", stick),
+            ("b/recipes/stick.json", stick),
+            (
+                "recipes/iron_ingot.json",
+                r#"{"type": "minecraft:smelting", "ingredient": {"item": "minecraft:iron_ore"},
+                    "result": "minecraft:iron_ingot"}"#,
+            ),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.into(), v.to_string()))
+        .collect();
+        let e = import_recipes(&files).unwrap();
+        let names: Vec<_> = e.rules.iter().map(|x| x.name.clone().unwrap()).collect();
+        assert_eq!(names, ["stick", "stick_2", "iron_ingot"]);
+        assert_eq!(e.rules[2].attr("machine"), Some("furnace"));
+        assert_eq!(e.rules[0].attr("machine"), None);
+    }
+
+    #[test]
+    fn numeric_file_names_round_trip() {
+        let smelt = r#"{"type": "minecraft:smelting", "ingredient": {"item": "minecraft:iron_ore"},
+            "result": "minecraft:iron_ingot"}"#;
+        let files: HashMap<PathBuf, String> =
+            [("recipes/1.json", smelt), ("recipes/2.json", smelt)]
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.to_string()))
+                .collect();
+        let e = import_recipes(&files).unwrap();
+        let mut names: Vec<_> = e.rules.iter().map(|x| x.name.clone().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, ["recipe_1", "recipe_2"]);
+        let again = Engine::from_string(&e.to_rule_string()).unwrap();
+        assert_eq!(again.rules, e.rules);
+    }
+
+    #[test]
+    fn alternatives_become_groups() {
+        let files: HashMap<PathBuf, String> = [(
+            "recipes/torch.json".into(),
+            r##"{"type": "minecraft:crafting_shaped", "pattern": ["c", "s"],
+                "key": {"c": [{"item": "minecraft:coal"}, {"item": "minecraft:charcoal"}],
+                        "s": {"item": "minecraft:stick"}},
+                "result": {"item": "minecraft:torch", "count": 4}}"##
+                .to_string(),
+        )]
+        .into_iter()
+        .collect();
+        let e = import_recipes(&files).unwrap();
+        assert_eq!(e.rules.len(), 1);
+        assert_eq!(
+            stacks(e.rules[0].groups[0].iter()),
+            [("coal".to_string(), 1), ("charcoal".to_string(), 1)]
+        );
+        assert_eq!(stacks(e.rules[0].reqs.iter()), [("stick".to_string(), 1)]);
+    }
+
+    #[test]
+    fn tags_become_tag_requirements() {
+        let files: HashMap<PathBuf, String> = [
+            (
+                "data/minecraft/tags/items/planks.json",
+                r#"{"values": ["minecraft:oak_planks", "minecraft:birch_planks"]}"#,
+            ),
+            (
+                "data/minecraft/recipes/stick.json",
+                r##"{"type": "minecraft:crafting_shaped", "pattern": ["#", "#"],
+                    "key": {"#": {"tag": "minecraft:planks"}},
+                    "result": {"item": "minecraft:stick", "count": 4}}"##,
+            ),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.into(), v.to_string()))
+        .collect();
+        let e = import_recipes(&files).unwrap();
+        assert_eq!(e.tags.len(), 1);
+        assert_eq!(e.tags[0].members.len(), 2);
+        assert_eq!(e.rules[0].groups[0].tag.as_deref(), Some("planks"));
+        assert_eq!(
+            stacks(e.rules[0].groups[0].iter())[0],
+            ("oak_planks".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn tags_keep_namespace() {
+        let files: HashMap<PathBuf, String> = [
+            (
+                "data/c/tags/items/ingots/iron.json",
+                r#"{"values": ["minecraft:iron_ingot", "mod:steel_ingot"]}"#,
+            ),
+            (
+                "data/c/tags/items/planks.json",
+                r#"{"values": ["mod:ash_planks"]}"#,
+            ),
+            (
+                "data/minecraft/tags/items/planks.json",
+                r#"{"values": ["minecraft:oak_planks"]}"#,
+            ),
+            (
+                "data/mod/recipes/gear.json",
+                r##"{"type": "minecraft:crafting_shaped", "pattern": ["#p#"],
+                    "key": {"#": {"tag": "c:ingots/iron"}, "p": {"tag": "c:planks"}},
+                    "result": {"item": "mod:gear"}}"##,
+            ),
+            (
+                "data/minecraft/recipes/stick.json",
+                r##"{"type": "minecraft:crafting_shaped", "pattern": ["#", "#"],
+                    "key": {"#": {"tag": "minecraft:planks"}},
+                    "result": {"item": "minecraft:stick", "count": 4}}"##,
+            ),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.into(), v.to_string()))
+        .collect();
+        let e = import_recipes(&files).unwrap();
+        let text = e.to_rule_string();
+        assert!(text.contains("tag c_ingots_iron = iron_ingot, mod_steel_ingot"));
+        assert!(text.contains("tag c_planks = mod_ash_planks"));
+        assert!(text.contains("tag planks = oak_planks"));
+        assert!(text.contains("gear: {2 #c_ingots_iron, #c_planks} -> mod_gear"));
+        assert!(text.contains("stick: {2 #planks} -> 4 stick"));
+        assert!(!text.contains("tag_"));
+    }
+}
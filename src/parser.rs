@@ -0,0 +1,1061 @@
+use core::fmt;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    iter::Peekable,
+    path::{Component, Path, PathBuf},
+    str::Chars,
+    sync::Arc,
+};
+
+use crate::{
+    engine::Engine,
+    fact::{CoreFact, CoreRule, Fact, Group, Rule, Stack, Tag},
+    unify,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl ParseError {
+    fn new(pos: Pos, message: impl Into<String>) -> Self {
+        ParseError {
+            file: None,
+            line: pos.line,
+            column: pos.column,
+            message: message.into(),
+        }
+    }
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        //Нулевая строка - ошибка относится ко всему файлу.
+        if self.line != 0 {
+            write!(f, "{}:{}:", self.line, self.column)?;
+        }
+        if self.file.is_some() || self.line != 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+    Colon,
+    Arrow,
+    At,
+    Eq,
+    Bang,
+    Pipe,
+    Hash,
+    Unknown(char),
+    Newline,
+    Eof,
+}
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(s) | Token::Number(s) => write!(f, "`{}`", s),
+            Token::Str(s) => write!(f, "{}", quote(s)),
+            Token::LBrace => write!(f, "`{{`"),
+            Token::RBrace => write!(f, "`}}`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Colon => write!(f, "`:`"),
+            Token::Arrow => write!(f, "`->`"),
+            Token::At => write!(f, "`@`"),
+            Token::Eq => write!(f, "`=`"),
+            Token::Bang => write!(f, "`!`"),
+            Token::Pipe => write!(f, "`|`"),
+            Token::Hash => write!(f, "`#`"),
+            Token::Unknown(c) => write!(f, "`{}`", c),
+            Token::Newline => write!(f, "end of line"),
+            Token::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+impl<'a> Lexer<'a> {
+    fn new(s: &'a str) -> Self {
+        Lexer {
+            chars: s.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+    fn tokenize(mut self) -> Vec<(Pos, Token)> {
+        let mut tokens = vec![];
+        loop {
+            let pos = Pos {
+                line: self.line,
+                column: self.column,
+            };
+            let Some(c) = self.bump() else {
+                tokens.push((pos, Token::Eof));
+                return tokens;
+            };
+            let token = match c {
+                '\n' => Token::Newline,
+                c if c.is_whitespace() => continue,
+                '/' if self.chars.peek() == Some(&'/') => {
+                    while self.chars.peek().is_some_and(|&c| c != '\n') {
+                        self.bump();
+                    }
+                    continue;
+                }
+                '-' if self.chars.peek() == Some(&'>') => {
+                    self.bump();
+                    Token::Arrow
+                }
+                '{' => Token::LBrace,
+                '}' => Token::RBrace,
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ',' => Token::Comma,
+                ':' => Token::Colon,
+                '@' => Token::At,
+                '=' => Token::Eq,
+                '!' => Token::Bang,
+                '|' => Token::Pipe,
+                '#' => Token::Hash,
+                '"' => {
+                    let mut s = String::new();
+                    loop {
+                        match self.chars.peek() {
+                            Some('"') => {
+                                self.bump();
+                                break Token::Str(s);
+                            }
+                            Some('\n') | None => break Token::Unknown('"'),
+                            //\" \\ \n, остальные обратные косые черты остаются как есть.
+                            Some('\\') => {
+                                self.bump();
+                                match self.chars.peek() {
+                                    Some(&c @ ('"' | '\\')) => s.push(c),
+                                    Some('n') => s.push('\n'),
+                                    _ => {
+                                        s.push('\\');
+                                        continue;
+                                    }
+                                }
+                                self.bump();
+                            }
+                            Some(&c) => {
+                                s.push(c);
+                                self.bump();
+                            }
+                        }
+                    }
+                }
+                c if is_ident_char(c) => {
+                    let mut s = String::from(c);
+                    while let Some(&c) = self.chars.peek().filter(|&&c| is_ident_char(c)) {
+                        s.push(c);
+                        self.bump();
+                    }
+                    //Десятичная дробь 1.5 - одно число, для значений атрибутов.
+                    let mut ahead = self.chars.clone();
+                    if s.chars().all(|c| c.is_ascii_digit())
+                        && ahead.next() == Some('.')
+                        && ahead.next().is_some_and(|c| c.is_ascii_digit())
+                    {
+                        s.push('.');
+                        self.bump();
+                        while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_digit()) {
+                            s.push(c);
+                            self.bump();
+                        }
+                    }
+                    if is_number(&s) {
+                        Token::Number(s)
+                    } else {
+                        Token::Ident(s)
+                    }
+                }
+                c => Token::Unknown(c),
+            };
+            tokens.push((pos, token));
+        }
+    }
+}
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+//Имя, которое лексер прочитает как один идентификатор.
+pub fn is_valid_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_ident_char) && !s.chars().all(|c| c.is_ascii_digit())
+}
+
+//Целое или десятичная дробь, которые лексер прочитает как Token::Number.
+pub fn is_number(s: &str) -> bool {
+    let (int, frac) = s.split_once('.').unwrap_or((s, "0"));
+    [int, frac]
+        .iter()
+        .all(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()))
+}
+//Строка в кавычках, которую лексер прочитает обратно как Token::Str(s).
+pub fn quote(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+//Разобранная, но ещё не добавленная в базу строка файла.
+#[derive(Debug, Clone)]
+enum Item {
+    Fact(CoreFact),
+    Include(Pos, String),
+    Start(Vec<CoreFact>),
+    Goal(Vec<(Pos, CoreFact)>), //С позицией каждой цели, для ошибки о повторе.
+    Tag(Pos, String, Vec<CoreFact>),
+    Rule(RuleItem),
+}
+//Требование с вариантами: a | b или #tag.
+#[derive(Debug, Clone)]
+enum GroupItem {
+    Options(Vec<(u32, CoreFact)>),
+    Tag(Pos, u32, String),
+}
+#[derive(Debug, Clone)]
+struct RuleItem {
+    pos: Pos,
+    name: Option<(Pos, String)>,
+    reqs: Vec<(u32, CoreFact)>,
+    groups: Vec<GroupItem>,
+    catalysts: Vec<CoreFact>,
+    negs: Vec<CoreFact>,
+    outs: Vec<(u32, CoreFact)>,
+    attrs: BTreeMap<String, String>,
+}
+
+struct Parser {
+    tokens: Vec<(Pos, Token)>,
+    cursor: usize,
+}
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.cursor].1
+    }
+    fn peek_nth(&self, n: usize) -> &Token {
+        &self.tokens[(self.cursor + n).min(self.tokens.len() - 1)].1
+    }
+    fn pos(&self) -> Pos {
+        self.tokens[self.cursor].0
+    }
+    fn bump(&mut self) -> (Pos, Token) {
+        let t = self.tokens[self.cursor].clone();
+        if t.1 != Token::Eof {
+            self.cursor += 1;
+        }
+        t
+    }
+    fn unexpected(&self, expected: &str) -> ParseError {
+        ParseError::new(
+            self.pos(),
+            format!("expected {}, found {}", expected, self.peek()),
+        )
+    }
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        if *self.peek() == token {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.unexpected(&token.to_string()))
+        }
+    }
+    fn ident(&mut self, expected: &str) -> Result<String, ParseError> {
+        match self.peek() {
+            Token::Ident(s) => {
+                let s = s.clone();
+                self.bump();
+                Ok(s)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+    //Факт: name или name(arg, ...). В аргументах имена с большой буквы - переменные.
+    fn fact(&mut self, expected: &str) -> Result<CoreFact, ParseError> {
+        let name = self.ident(expected)?;
+        if *self.peek() != Token::LParen {
+            return Ok(CoreFact::Symbol(name));
+        }
+        self.bump();
+        let mut v = vec![CoreFact::Symbol(name)];
+        loop {
+            let arg = match self.peek() {
+                Token::Number(n) => {
+                    let n = n.clone();
+                    self.bump();
+                    CoreFact::Symbol(n)
+                }
+                Token::Ident(s) if s.starts_with(char::is_uppercase) => {
+                    let s = s.clone();
+                    self.bump();
+                    CoreFact::Var(s)
+                }
+                _ => self.fact("argument")?,
+            };
+            v.push(arg);
+            match self.peek() {
+                Token::Comma => {
+                    self.bump();
+                }
+                Token::RParen => {
+                    self.bump();
+                    return Ok(CoreFact::Symbols(v));
+                }
+                _ => return Err(self.unexpected("`,` or `)`")),
+            }
+        }
+    }
+    fn ground_fact(&mut self) -> Result<CoreFact, ParseError> {
+        let pos = self.pos();
+        let f = self.fact("fact")?;
+        if f.is_ground() {
+            Ok(f)
+        } else {
+            Err(ParseError::new(
+                pos,
+                format!("`{}` can't contain variables here", f),
+            ))
+        }
+    }
+    fn skip_line(&mut self) {
+        while !matches!(self.peek(), Token::Newline | Token::Eof) {
+            self.bump();
+        }
+    }
+    fn line_end(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Token::Newline | Token::Eof => {
+                self.bump();
+                Ok(())
+            }
+            _ => Err(self.unexpected("end of line")),
+        }
+    }
+    fn count(&mut self) -> Result<u32, ParseError> {
+        Ok(match self.peek() {
+            Token::Number(n) => {
+                let (pos, n) = (self.pos(), n.clone());
+                self.bump();
+                match n.parse() {
+                    _ if n.contains('.') => {
+                        return Err(ParseError::new(
+                            pos,
+                            format!("count `{}` is not a whole number", n),
+                        ))
+                    }
+                    Ok(0) => return Err(ParseError::new(pos, "count must be positive")),
+                    Ok(n) => n,
+                    Err(_) => {
+                        return Err(ParseError::new(pos, format!("count `{}` is too large", n)))
+                    }
+                }
+            }
+            _ => 1,
+        })
+    }
+    fn stack(&mut self, expected: &str) -> Result<(u32, CoreFact), ParseError> {
+        Ok((self.count()?, self.fact(expected)?))
+    }
+    fn item(&mut self) -> Result<Item, ParseError> {
+        match self.peek() {
+            Token::LBrace => self.rule(None),
+            //name: {..} -> .. - именованное правило.
+            Token::Ident(s)
+                if *self.peek_nth(1) == Token::Colon && *self.peek_nth(2) == Token::LBrace =>
+            {
+                let name = (self.pos(), s.clone());
+                self.bump();
+                self.bump();
+                self.rule(Some(name))
+            }
+            Token::Ident(s) if *self.peek_nth(1) == Token::Colon => match s.as_str() {
+                "start" => {
+                    self.bump();
+                    self.bump();
+                    let mut facts = vec![self.ground_fact()?];
+                    while *self.peek() == Token::Comma {
+                        self.bump();
+                        facts.push(self.ground_fact()?);
+                    }
+                    Ok(Item::Start(facts))
+                }
+                "goal" => {
+                    self.bump();
+                    self.bump();
+                    let mut facts = vec![(self.pos(), self.ground_fact()?)];
+                    while *self.peek() == Token::Comma {
+                        self.bump();
+                        facts.push((self.pos(), self.ground_fact()?));
+                    }
+                    Ok(Item::Goal(facts))
+                }
+                _ => Err(ParseError::new(
+                    self.pos(),
+                    format!("unknown directive `{}`", s),
+                )),
+            },
+            //tag planks = oak_planks, birch_planks
+            Token::Ident(s)
+                if s == "tag"
+                    && matches!(self.peek_nth(1), Token::Ident(_))
+                    && *self.peek_nth(2) == Token::Eq =>
+            {
+                self.bump();
+                let (pos, name) = (self.pos(), self.ident("tag name")?);
+                self.bump();
+                let mut members = vec![self.ground_fact()?];
+                while *self.peek() == Token::Comma {
+                    self.bump();
+                    members.push(self.ground_fact()?);
+                }
+                Ok(Item::Tag(pos, name, members))
+            }
+            Token::Ident(s)
+                if s == "include" && !matches!(self.peek_nth(1), Token::Newline | Token::Eof) =>
+            {
+                self.bump();
+                match self.bump() {
+                    (pos, Token::Str(path)) => Ok(Item::Include(pos, path)),
+                    (pos, t) => Err(ParseError::new(
+                        pos,
+                        format!("expected file name in quotes, found {}", t),
+                    )),
+                }
+            }
+            Token::Ident(_) => Ok(Item::Fact(self.fact("fact")?)),
+            _ => Err(self.unexpected("fact or rule")),
+        }
+    }
+    fn rule(&mut self, name: Option<(Pos, String)>) -> Result<Item, ParseError> {
+        let pos = self.pos();
+        self.expect(Token::LBrace)?;
+        let mut reqs = vec![];
+        let mut groups = vec![];
+        let mut catalysts = vec![];
+        let mut negs = vec![];
+        while *self.peek() != Token::RBrace {
+            if *self.peek() == Token::Bang {
+                self.bump();
+                negs.push(self.fact("fact")?);
+            } else if *self.peek() == Token::At {
+                self.bump();
+                catalysts.push(self.fact("catalyst")?);
+            } else if *self.peek() == Token::Hash
+                || (matches!(self.peek(), Token::Number(_)) && *self.peek_nth(1) == Token::Hash)
+            {
+                let pos = self.pos();
+                let count = self.count()?;
+                self.bump();
+                groups.push(GroupItem::Tag(pos, count, self.ident("tag name")?));
+            } else {
+                let mut group = vec![self.stack("fact or `}`")?];
+                while *self.peek() == Token::Pipe {
+                    self.bump();
+                    group.push(self.stack("fact")?);
+                }
+                if group.len() == 1 {
+                    reqs.extend(group);
+                } else {
+                    groups.push(GroupItem::Options(group));
+                }
+            }
+            match self.peek() {
+                Token::Comma => {
+                    self.bump();
+                }
+                Token::RBrace => (),
+                _ => return Err(self.unexpected("`,`, `|` or `}`")),
+            }
+        }
+        self.bump();
+        self.expect(Token::Arrow)?;
+        let mut outs = vec![self.stack("fact")?];
+        while *self.peek() == Token::Comma {
+            self.bump();
+            outs.push(self.stack("fact")?);
+        }
+        let mut attrs = BTreeMap::new();
+        while *self.peek() == Token::At {
+            self.bump();
+            let pos = self.pos();
+            let key = self.ident("attribute name")?;
+            self.expect(Token::Eq)?;
+            let value = match self.peek() {
+                Token::Ident(s) | Token::Number(s) | Token::Str(s) => s.clone(),
+                _ => return Err(self.unexpected("attribute value")),
+            };
+            self.bump();
+            if attrs.insert(key.clone(), value).is_some() {
+                return Err(ParseError::new(
+                    pos,
+                    format!("attribute `{}` is set twice", key),
+                ));
+            }
+        }
+        Ok(Item::Rule(RuleItem {
+            pos,
+            name,
+            reqs,
+            groups,
+            catalysts,
+            negs,
+            outs,
+            attrs,
+        }))
+    }
+}
+
+//Откуда брать файлы для include.
+pub trait SourceLoader {
+    fn load(&self, path: &Path) -> Result<String, String>;
+}
+pub struct FileSystem;
+impl SourceLoader for FileSystem {
+    fn load(&self, path: &Path) -> Result<String, String> {
+        std::fs::read_to_string(path).map_err(|e| e.to_string())
+    }
+}
+//Загруженные через браузер файлы лежат без папок, поэтому ищем ещё и по имени файла.
+impl SourceLoader for HashMap<PathBuf, String> {
+    fn load(&self, path: &Path) -> Result<String, String> {
+        self.get(path)
+            .or_else(|| {
+                let name = path.file_name()?;
+                self.iter()
+                    .find(|(p, _)| p.file_name() == Some(name))
+                    .map(|(_, s)| s)
+            })
+            .cloned()
+            .ok_or_else(|| "file not found".to_string())
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir if res.file_name().is_some() => {
+                res.pop();
+            }
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+//Файл из набора, который не подключается ни одним другим файлом (или первый, если таких нет).
+pub fn root_file(files: &HashMap<PathBuf, String>) -> Option<PathBuf> {
+    let included: HashSet<_> = files
+        .values()
+        .flat_map(|s| {
+            let tokens = Lexer::new(s).tokenize();
+            tokens
+                .windows(2)
+                .filter_map(|w| match (&w[0].1, &w[1].1) {
+                    (Token::Ident(i), Token::Str(p)) if i == "include" => {
+                        Path::new(p).file_name().map(|x| x.to_owned())
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let mut roots: Vec<_> = files
+        .keys()
+        .filter(|p| p.file_name().is_some_and(|x| !included.contains(x)))
+        .collect();
+    if roots.is_empty() {
+        roots = files.keys().collect();
+    }
+    roots.sort();
+    roots.first().map(|x| x.to_path_buf())
+}
+
+struct Builder<'a> {
+    loader: &'a dyn SourceLoader,
+    //Файлы, которые разбираются сейчас, для поиска циклов.
+    stack: Vec<PathBuf>,
+    loaded: HashSet<PathBuf>,
+    errors: Vec<ParseError>,
+    facts: HashMap<CoreFact, Fact>,
+    all_facts: Vec<Fact>,
+    rules: Vec<Rule>,
+    rule_names: HashSet<String>,
+    tags: Vec<Tag>,
+    starting_facts: HashSet<Fact>,
+    goals: Vec<Fact>,
+}
+impl<'a> Builder<'a> {
+    fn new(loader: &'a dyn SourceLoader) -> Self {
+        Builder {
+            loader,
+            stack: vec![],
+            loaded: HashSet::new(),
+            errors: vec![],
+            facts: HashMap::new(),
+            all_facts: vec![],
+            rules: vec![],
+            rule_names: HashSet::new(),
+            tags: vec![],
+            starting_facts: HashSet::new(),
+            goals: vec![],
+        }
+    }
+    fn fact(&mut self, fact: CoreFact) -> Fact {
+        if let Some(f) = self.facts.get(&fact) {
+            return f.clone();
+        }
+        let f = Arc::new(fact.clone());
+        self.facts.insert(fact, f.clone());
+        self.all_facts.push(f.clone());
+        f
+    }
+    fn source(&mut self, s: &str, file: Option<&Path>) {
+        let mut parser = Parser {
+            tokens: Lexer::new(s).tokenize(),
+            cursor: 0,
+        };
+        if let Some(file) = file {
+            self.loaded.insert(file.to_path_buf());
+            self.stack.push(file.to_path_buf());
+        }
+        while *parser.peek() != Token::Eof {
+            if *parser.peek() == Token::Newline {
+                parser.bump();
+                continue;
+            }
+            let res = match parser
+                .item()
+                .and_then(|item| parser.line_end().map(|_| item))
+            {
+                Ok(item) => self.add(item).map_err(|e| ParseError::new(e.0, e.1)),
+                Err(e) => {
+                    parser.skip_line();
+                    Err(e)
+                }
+            };
+            if let Err(mut e) = res {
+                e.file = file.map(Path::to_path_buf);
+                self.errors.push(e);
+            }
+        }
+        if file.is_some() {
+            self.stack.pop();
+        }
+    }
+    fn include(&mut self, path: &str) -> Result<(), String> {
+        let path = match self.stack.last().and_then(|x| x.parent()) {
+            Some(dir) => normalize(&dir.join(path)),
+            None => normalize(Path::new(path)),
+        };
+        if let Some(i) = self.stack.iter().position(|x| *x == path) {
+            let cycle: Vec<_> = self.stack[i..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|x| x.display().to_string())
+                .collect();
+            return Err(format!("include cycle: {}", cycle.join(" -> ")));
+        }
+        if self.loaded.contains(&path) {
+            return Ok(());
+        }
+        let s = self
+            .loader
+            .load(&path)
+            .map_err(|e| format!("cannot include `{}`: {}", path.display(), e))?;
+        self.source(&s, Some(&path));
+        Ok(())
+    }
+    fn add(&mut self, item: Item) -> Result<(), (Pos, String)> {
+        match item {
+            Item::Fact(name) => {
+                self.fact(name);
+            }
+            Item::Include(pos, path) => {
+                self.include(&path).map_err(|e| (pos, e))?;
+            }
+            Item::Start(names) => {
+                for name in names {
+                    let f = self.fact(name);
+                    self.starting_facts.insert(f);
+                }
+            }
+            //Цели накапливаются, как и начальные факты, но без повторов.
+            Item::Goal(names) => {
+                for (pos, name) in names {
+                    let f = self.fact(name);
+                    if self.goals.contains(&f) {
+                        return Err((pos, format!("goal `{}` is already set", f)));
+                    }
+                    self.goals.push(f);
+                }
+            }
+            Item::Tag(pos, name, members) => {
+                if self.tags.iter().any(|x| x.name == name) {
+                    return Err((pos, format!("tag `{}` is already declared", name)));
+                }
+                let members = members.into_iter().map(|x| self.fact(x)).collect();
+                self.tags.push(Tag { name, members });
+            }
+            Item::Rule(rule) => {
+                if let Some((pos, name)) = &rule.name {
+                    if !self.rule_names.insert(name.clone()) {
+                        return Err((*pos, format!("rule `{}` is already declared", name)));
+                    }
+                }
+                let reqs: Vec<_> = rule
+                    .reqs
+                    .into_iter()
+                    .map(|(count, x)| Stack::new(self.fact(x), count))
+                    .collect();
+                let mut groups = vec![];
+                for g in rule.groups {
+                    groups.push(match g {
+                        GroupItem::Options(g) => Group::new(
+                            g.into_iter()
+                                .map(|(count, x)| Stack::new(self.fact(x), count))
+                                .collect(),
+                        ),
+                        GroupItem::Tag(pos, count, name) => {
+                            match self.tags.iter().find(|x| x.name == name) {
+                                Some(tag) => Group::tagged(tag, count),
+                                None => return Err((pos, format!("unknown tag `{}`", name))),
+                            }
+                        }
+                    });
+                }
+                let catalysts: Vec<_> = rule.catalysts.into_iter().map(|x| self.fact(x)).collect();
+                let negs: Vec<_> = rule.negs.into_iter().map(|x| self.fact(x)).collect();
+                let outs: Vec<_> = rule
+                    .outs
+                    .into_iter()
+                    .map(|(count, x)| Stack::new(self.fact(x), count))
+                    .collect();
+                let core = CoreRule {
+                    name: rule.name.map(|x| x.1),
+                    reqs,
+                    groups,
+                    catalysts,
+                    negs,
+                    outs,
+                    attrs: rule.attrs,
+                };
+                //Переменные должны получать значения из обычных требований.
+                if let Some(var) = unify::unbound_vars(&core).first() {
+                    return Err((
+                        rule.pos,
+                        format!("variable `{}` is not bound by a requirement", var),
+                    ));
+                }
+                self.rules.push(Arc::new(core));
+            }
+        }
+        Ok(())
+    }
+    fn finish(self) -> Result<Engine, Vec<ParseError>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        let mut engine = Engine::new(self.all_facts, self.starting_facts, self.rules);
+        engine.goals = self.goals;
+        engine.tags = self.tags;
+        match engine.strata() {
+            Ok(_) => Ok(engine),
+            Err(message) => Err(vec![ParseError {
+                file: None,
+                line: 0,
+                column: 0,
+                message,
+            }]),
+        }
+    }
+}
+
+//Факт из строки, например из JSON: "plate(iron)".
+pub fn parse_fact(s: &str) -> Option<CoreFact> {
+    let mut parser = Parser {
+        tokens: Lexer::new(s).tokenize(),
+        cursor: 0,
+    };
+    let f = parser.fact("fact").ok()?;
+    (*parser.peek() == Token::Eof).then_some(f)
+}
+pub fn parse(s: &str) -> Result<Engine, Vec<ParseError>> {
+    let files: HashMap<PathBuf, String> = HashMap::new();
+    let mut builder = Builder::new(&files);
+    builder.source(s, None);
+    builder.finish()
+}
+pub fn parse_file(path: &Path, loader: &dyn SourceLoader) -> Result<Engine, Vec<ParseError>> {
+    let mut builder = Builder::new(loader);
+    let path = normalize(path);
+    match loader.load(&path) {
+        Ok(s) => builder.source(&s, Some(&path)),
+        Err(e) => builder.errors.push(ParseError {
+            file: Some(path),
+            line: 0,
+            column: 0,
+            message: e,
+        }),
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(s: &str) -> Vec<(usize, usize, String)> {
+        parse(s)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.line, e.column, e.message))
+            .collect()
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(
+            errors("{a} -> b\n{c -> d\n"),
+            [(2, 4, "expected `,`, `|` or `}`, found `->`".to_string())]
+        );
+        assert_eq!(
+            errors("a\n  {a} => b\n"),
+            [(2, 7, "expected `->`, found `=`".to_string())]
+        );
+        assert_eq!(
+            errors("{a} -> b\n\n\n{b} ->\n"),
+            [(4, 7, "expected fact, found end of line".to_string())]
+        );
+    }
+
+    #[test]
+    fn errors_on_every_bad_line() {
+        let lines: Vec<usize> = errors("{a -> b\nc\n{c} => d\n{d} -> e\n")
+            .into_iter()
+            .map(|e| e.0)
+            .collect();
+        assert_eq!(lines, [1, 3]);
+    }
+
+    #[test]
+    fn trailing_comments() {
+        let e = parse("{a} -> b // note\n// only a comment\nc // and here\n").unwrap();
+        assert_eq!(e.rules.len(), 1);
+        let facts: Vec<String> = e.all_possible_facts.iter().map(|x| x.to_string()).collect();
+        assert_eq!(facts, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn counts() {
+        let e = parse("{2 oak_plank} -> 4 stick\n").unwrap();
+        assert_eq!(e.rules[0].reqs[0].count, 2);
+        assert_eq!(
+            errors("{0 a} -> b\n{a} -> 99999999999 b\n"),
+            [
+                (1, 2, "count must be positive".to_string()),
+                (2, 8, "count `99999999999` is too large".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn several_outputs() {
+        let e = parse("{ore} -> iron_ingot, 2 slag\n").unwrap();
+        let outs: Vec<(String, u32)> = e.rules[0]
+            .outs
+            .iter()
+            .map(|x| (x.fact.to_string(), x.count))
+            .collect();
+        assert_eq!(
+            outs,
+            [("iron_ingot".to_string(), 1), ("slag".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn start_and_goal() {
+        let e =
+            parse("{oak_wood} -> oak_planks\nstart: oak_wood, sand\ngoal: oak_planks\n").unwrap();
+        let mut start: Vec<String> = e.starting_facts.iter().map(|x| x.to_string()).collect();
+        start.sort();
+        assert_eq!(start, ["oak_wood", "sand"]);
+        let e = parse("a\nb\nc\ngoal: a, b\ngoal: c\n").unwrap();
+        let goals: Vec<String> = e.goals.iter().map(|x| x.to_string()).collect();
+        assert_eq!(goals, ["a", "b", "c"]);
+        assert_eq!(
+            errors("goal: a\ngoal: b, a\ntarget: c\n"),
+            [
+                (2, 10, "goal `a` is already set".to_string()),
+                (3, 1, "unknown directive `target`".to_string())
+            ]
+        );
+        assert_eq!(
+            errors("goal: a, b, a\n"),
+            [(1, 13, "goal `a` is already set".to_string())]
+        );
+    }
+
+    fn files(list: &[(&str, &str)]) -> HashMap<PathBuf, String> {
+        list.iter()
+            .map(|(k, v)| (k.into(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn includes_resolve_against_including_file() {
+        let files = files(&[
+            ("main.txt", "include \"a/x.txt\"\ninclude \"b/x.txt\"\n"),
+            ("a/x.txt", "include \"common.txt\"\n"),
+            ("a/common.txt", "{ore} -> iron\nstart: ore\n"),
+            ("b/x.txt", "include \"common.txt\"\n"),
+            ("b/common.txt", "{iron, ore} -> sword\n"),
+        ]);
+        let e = parse_file(Path::new("main.txt"), &files).unwrap();
+        let facts: Vec<String> = e.all_possible_facts.iter().map(|x| x.to_string()).collect();
+        assert_eq!(facts, ["ore", "iron", "sword"]);
+        assert_eq!(e.rules.len(), 2);
+        assert_eq!(e.rules[0].reqs[0].fact, e.rules[1].reqs[1].fact);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let files = files(&[
+            ("main.txt", "include \"a.txt\"\nstart: x\n"),
+            ("a.txt", "{x} -> y\ninclude \"b.txt\"\n"),
+            ("b.txt", "\ninclude \"a.txt\"\n"),
+        ]);
+        let e = parse_file(Path::new("main.txt"), &files).unwrap_err();
+        assert_eq!(
+            e,
+            [ParseError {
+                file: Some("b.txt".into()),
+                line: 2,
+                column: 9,
+                message: "include cycle: a.txt -> b.txt -> a.txt".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn names_and_attributes() {
+        let e = parse(
+            "smelt_iron: {iron_ore} -> iron_ingot @machine=furnace @time=10\n\
+             {a} -> b @note=\"two words\"\n",
+        )
+        .unwrap();
+        let r = &e.rules[0];
+        assert_eq!(r.name.as_deref(), Some("smelt_iron"));
+        assert_eq!(r.attr("machine"), Some("furnace"));
+        assert_eq!(r.attr("time"), Some("10"));
+        assert_eq!(e.rules[1].name, None);
+        assert_eq!(e.rules[1].attr("note"), Some("two words"));
+        assert_eq!(
+            errors("a: {x} -> y\na: {y} -> z\n{x} -> y @k=1 @k=2\n"),
+            [
+                (2, 1, "rule `a` is already declared".to_string()),
+                (3, 16, "attribute `k` is set twice".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn groups() {
+        let e = parse("{coal | 2 charcoal, stick} -> 4 torch\n").unwrap();
+        let group: Vec<String> = e.rules[0].groups[0].iter().map(|x| x.to_string()).collect();
+        assert_eq!(group, ["coal", "2 charcoal"]);
+        assert_eq!(e.rules[0].reqs.len(), 1);
+    }
+
+    #[test]
+    fn tags() {
+        let e = parse("tag planks = oak, birch\n{2 #planks} -> 4 stick\n").unwrap();
+        assert_eq!(e.tags[0].members.len(), 2);
+        let group = &e.rules[0].groups[0];
+        assert_eq!(group.tag.as_deref(), Some("planks"));
+        let options: Vec<String> = group.iter().map(|x| x.to_string()).collect();
+        assert_eq!(options, ["2 oak", "2 birch"]);
+        assert_eq!(
+            errors("{a, #nope} -> b\ntag t = a\ntag t = b\n"),
+            [
+                (1, 5, "unknown tag `nope`".to_string()),
+                (3, 5, "tag `t` is already declared".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_attributes_round_trip() {
+        let e = parse(r#"{a} -> b @note="say \"hi\" \\ c:\dir\nnext""#).unwrap();
+        let note = e.rules[0].attr("note").unwrap();
+        assert_eq!(note, "say \"hi\" \\ c:\\dir\nnext");
+        assert_eq!(quote(note), r#""say \"hi\" \\ c:\\dir\nnext""#);
+        let again = parse(&e.rules[0].to_string()).unwrap();
+        assert_eq!(again.rules[0].attr("note"), Some(note));
+    }
+
+    #[test]
+    fn decimal_attributes() {
+        let e = parse("{ore} -> iron @cost=1.5 @time=10\n{a} -> b @cost=\"0.5\"\n").unwrap();
+        assert_eq!(e.rules[0].attr("cost"), Some("1.5"));
+        assert_eq!(e.rules[0].to_string(), "{ore} -> iron @cost=1.5 @time=10");
+        assert_eq!(e.rules[1].to_string(), "{a} -> b @cost=0.5");
+        assert!(is_number("0.25") && !is_number("1.") && !is_number(".5"));
+        assert_eq!(
+            errors("{1.5 a} -> b\n{a} -> b @cost=1.\n"),
+            [
+                (1, 2, "count `1.5` is not a whole number".to_string()),
+                (2, 17, "expected end of line, found `.`".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, BinaryHeap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    engine::Engine,
+    fact::{Fact, Rule},
+    ruletree::{RuleTree, Source},
+};
+
+//Самый дешёвый вывод цели: обобщённый алгоритм Дейкстры (Кнут) по графу И/ИЛИ.
+//Стоимость плана - сумма @cost (по умолчанию 1) различных правил дерева: общая подцель выводится один раз.
+//Поиск же складывает стоимости поддеревьев и платит за общую подцель при каждом использовании,
+//так что при общих подцелях найденный план может быть не самым дешёвым.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub tree: RuleTree,
+    pub cost: f64,
+}
+
+//Для BinaryHeap: меньшая стоимость - больший приоритет.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry(f64, usize);
+impl Eq for Entry {}
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
+}
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+//Требование правила: один факт или группа, где хватит любого варианта.
+struct Slot {
+    facts: Vec<Fact>,
+    chosen: Option<Fact>,
+}
+
+impl Plan {
+    pub fn cheapest(engine: &Engine, target: &Fact) -> Option<Plan> {
+        //Отрицания проверяются по всем выводимым фактам, как в стратифицированном выводе.
+        let model = engine.model();
+        let instances: Vec<_> = engine
+            .ground_instances()
+            .into_iter()
+            .filter(|(r, _)| !r.negs.iter().any(|x| model.contains(x)))
+            .collect();
+        let mut slots: Vec<Vec<Slot>> = instances
+            .iter()
+            .map(|(r, _)| {
+                r.reqs
+                    .iter()
+                    .map(|x| vec![x.fact.clone()])
+                    .chain(
+                        r.groups
+                            .iter()
+                            .map(|g| g.iter().map(|x| x.fact.clone()).collect()),
+                    )
+                    .chain(r.catalysts.iter().map(|x| vec![x.clone()]))
+                    .map(|facts| Slot {
+                        facts,
+                        chosen: None,
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut users: HashMap<Fact, Vec<(usize, usize)>> = HashMap::new();
+        for (i, s) in slots.iter().enumerate() {
+            for (j, slot) in s.iter().enumerate() {
+                for f in &slot.facts {
+                    users.entry(f.clone()).or_default().push((i, j));
+                }
+            }
+        }
+        let mut remaining: Vec<usize> = slots.iter().map(|x| x.len()).collect();
+        let mut slot_cost: Vec<f64> = instances.iter().map(|(r, _)| r.cost()).collect();
+
+        let mut ids: HashMap<Fact, usize> = HashMap::new();
+        let mut facts: Vec<Fact> = vec![];
+        fn id(ids: &mut HashMap<Fact, usize>, facts: &mut Vec<Fact>, f: &Fact) -> usize {
+            *ids.entry(f.clone()).or_insert_with(|| {
+                facts.push(f.clone());
+                facts.len() - 1
+            })
+        }
+        let mut dist: HashMap<usize, f64> = HashMap::new();
+        let mut best: HashMap<usize, usize> = HashMap::new();
+        let mut done: HashSet<usize> = HashSet::new();
+        let mut heap = BinaryHeap::new();
+        let mut relax = |f: usize, cost: f64, rule: Option<usize>, heap: &mut BinaryHeap<Entry>| {
+            if dist.get(&f).is_none_or(|&d| cost < d) {
+                dist.insert(f, cost);
+                match rule {
+                    Some(r) => best.insert(f, r),
+                    None => best.remove(&f),
+                };
+                heap.push(Entry(cost, f));
+            }
+        };
+        for f in &engine.starting_facts {
+            relax(id(&mut ids, &mut facts, f), 0.0, None, &mut heap);
+        }
+        //Правила без требований срабатывают сразу.
+        for (i, (r, _)) in instances.iter().enumerate() {
+            if remaining[i] == 0 {
+                for out in &r.outs {
+                    relax(
+                        id(&mut ids, &mut facts, &out.fact),
+                        slot_cost[i],
+                        Some(i),
+                        &mut heap,
+                    );
+                }
+            }
+        }
+        let target_id = id(&mut ids, &mut facts, target);
+        while let Some(Entry(cost, f)) = heap.pop() {
+            if !done.insert(f) {
+                continue;
+            }
+            if f == target_id {
+                break;
+            }
+            let fact = facts[f].clone();
+            for &(i, j) in users.get(&fact).into_iter().flatten() {
+                //Первый готовый вариант группы - самый дешёвый.
+                let slot = &mut slots[i][j];
+                if slot.chosen.is_some() {
+                    continue;
+                }
+                slot.chosen = Some(fact.clone());
+                slot_cost[i] += cost;
+                remaining[i] -= 1;
+                if remaining[i] == 0 {
+                    for out in &instances[i].0.outs {
+                        let o = id(&mut ids, &mut facts, &out.fact);
+                        if !done.contains(&o) {
+                            relax(o, slot_cost[i], Some(i), &mut heap);
+                        }
+                    }
+                }
+            }
+        }
+        if !done.contains(&target_id) || engine.starting_facts.contains(target) {
+            return None;
+        }
+
+        fn source(
+            f: &Fact,
+            ids: &HashMap<Fact, usize>,
+            best: &HashMap<usize, usize>,
+            instances: &[(Rule, Rule)],
+            slots: &[Vec<Slot>],
+            used: &mut BTreeSet<usize>,
+        ) -> Source {
+            let Some(&i) = ids.get(f).and_then(|x| best.get(x)) else {
+                return Source::BasicFact(f.clone());
+            };
+            used.insert(i);
+            let sources = slots[i]
+                .iter()
+                .filter_map(|x| x.chosen.as_ref())
+                .map(|x| source(x, ids, best, instances, slots, used))
+                .collect();
+            Source::Rule(Arc::new(RuleTree {
+                fact: f.clone(),
+                rule: Some(instances[i].0.clone()),
+                sources,
+            }))
+        }
+        let mut used = BTreeSet::new();
+        let Source::Rule(tree) = source(target, &ids, &best, &instances, &slots, &mut used) else {
+            return None;
+        };
+        Some(Plan {
+            tree: (*tree).clone(),
+            cost: used.iter().map(|&i| instances[i].0.cost()).sum(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(src: &str, target: &str) -> Option<(f64, Vec<String>)> {
+        let e = Engine::from_string(src).unwrap();
+        let f = e
+            .all_possible_facts
+            .iter()
+            .find(|x| x.to_string() == target);
+        let plan = e.cheapest_plan(f.unwrap())?;
+        Some((
+            plan.cost,
+            plan.tree.rules().iter().map(|x| x.to_string()).collect(),
+        ))
+    }
+
+    #[test]
+    fn cheapest_derivation_wins() {
+        let src = "{iron} -> stick @cost=5\n{wood} -> 4 plank\n{2 plank} -> 4 stick\n\
+                   start: wood, iron\n";
+        let (cost, rules) = plan(src, "stick").unwrap();
+        assert_eq!(cost, 2.0);
+        assert_eq!(rules, ["{wood} -> 4 plank", "{2 plank} -> 4 stick"]);
+        let (cost, rules) = plan(&src.replace("@cost=5", "@cost=1"), "stick").unwrap();
+        assert_eq!(cost, 1.0);
+        assert_eq!(rules, ["{iron} -> stick @cost=1"]);
+        assert_eq!(plan(src, "wood"), None);
+        assert_eq!(plan(&format!("{}gold\n", src), "gold"), None);
+    }
+
+    #[test]
+    fn cheapest_group_option() {
+        let src = "{ore} -> coal @cost=3\n{log} -> charcoal\n{coal | charcoal} -> torch\n\
+                   start: ore, log\n";
+        let (cost, rules) = plan(src, "torch").unwrap();
+        assert_eq!(cost, 2.0);
+        assert_eq!(rules, ["{log} -> charcoal", "{coal | charcoal} -> torch"]);
+    }
+
+    #[test]
+    fn tie_goes_to_rule_declared_first() {
+        let src = "{a} -> t @note=first\n{a} -> t @note=second\n{a} -> m\n{m} -> t @cost=0\n\
+                   start: a\n";
+        let (cost, rules) = plan(src, "t").unwrap();
+        assert_eq!(cost, 1.0);
+        assert_eq!(rules, ["{a} -> t @note=first"]);
+    }
+
+    #[test]
+    fn shared_subgoal_is_paid_once() {
+        let src =
+            "{wood} -> plank @cost=2\n{plank} -> stick\n{plank, stick} -> sign\nstart: wood\n";
+        let (cost, rules) = plan(src, "sign").unwrap();
+        assert_eq!(rules.len(), 4);
+        assert_eq!(cost, 4.0);
+    }
+}
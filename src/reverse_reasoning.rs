@@ -1,24 +1,42 @@
+use core::fmt;
 use std::{
     collections::{HashMap, HashSet},
     mem::swap,
-    sync::Arc,
+    sync::{Arc, OnceLock, RwLock},
 };
 
 use crate::{
     direct_reasoning::{FactState, NodeColoring, RuleState},
     engine::Engine,
-    fact::{Fact, Rule},
+    fact::{CoreFact, Fact, Rule},
+    ruletree::{RuleTree, Source},
+    unify,
 };
-#[derive(Debug, Clone)]
+//Узлы целей общие, поэтому копия поиска делила бы их с оригиналом: Clone нет.
+#[derive(Debug)]
 pub struct ReverseReasoning {
     all_facts: Vec<Fact>,
-    target_fact: Fact,
+    //Несколько целей доказываются под общим корнем All.
+    targets: Vec<Fact>,
     //starting_facts: Vec<Fact>,
     pub all_rules: Vec<Rule>,
     //pub rules: Arc<Engine>,
     pub root: Box<Node>,
     pub starting_facts: HashSet<Fact>,
     pub reversed_rules: HashMap<Fact, Vec<Rule>>,
+    //Правила с переменными подставляются под конкретную цель при раскрытии узла.
+    schemas: Vec<Rule>,
+    constants: Vec<CoreFact>,
+    instances: HashMap<Rule, Rule>,
+    //Все выводимые факты, для объяснения, почему цель не вывелась. Считаются при первом объяснении.
+    model: OnceLock<Arc<HashSet<Fact>>>,
+    //Один узел на факт, с момента создания: и пока ищется, и когда доказан или недоказуем.
+    table: HashMap<Fact, Goal>,
+    //Цели, пройденные на этом шаге, с результатом. Пока цель раскрывается, в ней Potential:
+    //повторный заход в неё - цикл.
+    visited: HashMap<Fact, RecResult>,
+    //Изменилось ли что-то на этом шаге.
+    changed: bool,
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RevStepResult {
@@ -28,45 +46,95 @@ pub enum RevStepResult {
 }
 impl ReverseReasoning {
     pub fn new(rules: &Engine, target_fact: Fact) -> Self {
+        Self::with_targets(rules, vec![target_fact])
+    }
+    pub fn with_targets(rules: &Engine, targets: Vec<Fact>) -> Self {
         let starting_facts = rules.starting_facts.iter().cloned().collect();
         let mut reversed_rules: HashMap<Fact, Vec<Rule>> = HashMap::new();
-        for (res_fact, rule) in rules.rules.iter().map(|x| (x.out.clone(), x.clone())) {
-            if reversed_rules.contains_key(&res_fact) {
-                reversed_rules
-                    .get_mut(&res_fact)
-                    .unwrap()
-                    .push(rule.clone());
-            } else {
-                reversed_rules.insert(res_fact.clone(), vec![rule]);
+        for rule in rules.rules.iter().filter(|x| x.is_ground()) {
+            for res_fact in rule.outs.iter().map(|x| x.fact.clone()) {
+                let v = reversed_rules.entry(res_fact).or_default();
+                if !v.contains(rule) {
+                    v.push(rule.clone());
+                }
             }
         }
+        let root = match targets.as_slice() {
+            [target] => Node::prove(target),
+            _ => Node {
+                node_info: NodeInfo::All(
+                    targets.iter().map(Node::prove).collect(),
+                    RecResult::Potential,
+                ),
+            },
+        };
         Self {
             all_facts: rules.all_possible_facts.clone(),
             all_rules: rules.rules.clone(),
-            root: Box::new(Node {
-                available_rules: Arc::new(rules.rules.iter().cloned().collect()),
-                node_info: NodeInfo::FactToProve(target_fact.clone()),
-            }),
+            root: Box::new(root),
             starting_facts,
             reversed_rules,
-            target_fact,
+            targets,
+            schemas: rules
+                .rules
+                .iter()
+                .filter(|x| !x.is_ground())
+                .cloned()
+                .collect(),
+            constants: unify::constants(
+                rules.all_possible_facts.iter().chain(&rules.starting_facts),
+            ),
+            instances: HashMap::new(),
+            model: OnceLock::new(),
+            table: HashMap::new(),
+            visited: HashMap::new(),
+            changed: false,
         }
     }
     pub fn step(&mut self) -> RevStepResult {
         //println!("{:?}", self.root);
         let mut n = Box::new(Node {
-            available_rules: Arc::new(HashSet::new()),
             node_info: NodeInfo::Empty,
         });
         swap(&mut n, &mut self.root);
+        self.visited.clear();
+        self.changed = false;
         let t = self.rec_iterate(&mut n);
         swap(&mut n, &mut self.root);
+        if t == RecResult::Potential && !self.changed {
+            self.complete();
+        }
         match t {
             RecResult::Potential => RevStepResult::Iterated,
             RecResult::Found => RevStepResult::Found,
             RecResult::DeadEnd => RevStepResult::NotProved,
         }
     }
+    //Шаг ничего не изменил: пройденные цели ждут только друг друга по циклам, доказать их нечем.
+    //Цели, которые ждут отрицания, остаются: сначала закончится цель под отрицанием,
+    //а она по стратификации от них не зависит.
+    fn complete(&mut self) {
+        let pending: Vec<Fact> = self
+            .visited
+            .iter()
+            .filter(|x| *x.1 == RecResult::Potential)
+            .map(|x| x.0.clone())
+            .collect();
+        let mut stuck: Vec<&Goal> = pending
+            .iter()
+            .map(|f| &self.table[f])
+            .filter(|g| !g.read().waits_for_negation(&mut HashSet::new()))
+            .collect();
+        //Без стратификации ждать нечего: закрываем все, чтобы поиск закончился.
+        if stuck.is_empty() {
+            stuck = pending.iter().map(|f| &self.table[f]).collect();
+        }
+        for g in stuck {
+            if let NodeInfo::Or(_, _, status) = &mut g.0.write().unwrap().node_info {
+                *status = RecResult::DeadEnd;
+            }
+        }
+    }
     pub fn build_tree(&mut self, coloring: &NodeColoring) -> RevStepResult {
         loop {
             let t = self.step();
@@ -86,59 +154,45 @@ impl ReverseReasoning {
         for i in &self.all_rules {
             rc.insert(i.clone(), RuleState::None);
         }
-        Self::rec_recoloring(&self.root, &mut fc, &mut rc);
+        Self::rec_recoloring(
+            &self.root,
+            &mut fc,
+            &mut rc,
+            &self.instances,
+            &mut HashSet::new(),
+        );
         for i in &self.starting_facts {
             fc.insert(i.clone(), FactState::Starting);
         }
 
+        for (t, node) in self.target_nodes() {
+            let state = match node.status() {
+                RecResult::Potential => FactState::Target,
+                RecResult::Found => FactState::TargetVisited,
+                RecResult::DeadEnd => FactState::TargetNotPossible,
+            };
+            fc.insert(t.clone(), state);
+        }
+    }
+    //Цели вместе с узлами, которые их доказывают.
+    fn target_nodes(&self) -> Vec<(&Fact, &Node)> {
         match &self.root.node_info {
-            NodeInfo::Or(f, _, s) => {
-                if *s == RecResult::Found && f == &self.target_fact {
-                    fc.insert(self.target_fact.clone(), FactState::TargetVisited);
-                } else if *s == RecResult::DeadEnd { 
-                    fc.insert(self.target_fact.clone(), FactState::TargetNotPossible);
-                } else {
-                    fc.insert(self.target_fact.clone(), FactState::Target);
-                }
-            }
-            NodeInfo::And(f, _, _, s) => {
-                if *s == RecResult::Found && f == &self.target_fact {
-                    fc.insert(self.target_fact.clone(), FactState::TargetVisited);
-                }
-                else if *s == RecResult::DeadEnd { 
-                    fc.insert(self.target_fact.clone(), FactState::TargetNotPossible);
-                }
-                else {
-                    fc.insert(self.target_fact.clone(), FactState::Target);
-                }
-            }
-            NodeInfo::FactToProve(_) => {
-                fc.insert(self.target_fact.clone(), FactState::Target);
-            }
-            NodeInfo::ProvenFact(f) => {
-                if f == &self.target_fact {
-                    fc.insert(self.target_fact.clone(), FactState::TargetVisited);
-                } else {
-                    fc.insert(self.target_fact.clone(), FactState::Target);
-                }
-            }
-            NodeInfo::DeadEnd(_) => {
-                fc.insert(self.target_fact.clone(), FactState::TargetNotPossible);
-            }
-            NodeInfo::Empty => {
-                fc.insert(self.target_fact.clone(), FactState::Target);
-            }
-        };
+            NodeInfo::All(v, _) => self.targets.iter().zip(v).collect(),
+            _ => self.targets.iter().map(|t| (t, &*self.root)).collect(),
+        }
     }
+    //seen - цели, которые уже раскрашены: узел цели общий для всех ссылок на неё.
     fn rec_recoloring(
         node: &Node,
         facts: &mut HashMap<Fact, FactState>,
         rules: &mut HashMap<Rule, RuleState>,
+        instances: &HashMap<Rule, Rule>,
+        seen: &mut HashSet<Fact>,
     ) {
         match &node.node_info {
             NodeInfo::Or(t, r, q) => {
                 for i in r {
-                    Self::rec_recoloring(i, facts, rules);
+                    Self::rec_recoloring(i, facts, rules, instances, seen);
                 }
                 let fs = match q {
                     RecResult::Potential => FactState::Visited,
@@ -149,14 +203,14 @@ impl ReverseReasoning {
             }
             NodeInfo::And(f, r, n, q) => {
                 for i in n {
-                    Self::rec_recoloring(i, facts, rules);
+                    Self::rec_recoloring(i, facts, rules, instances, seen);
                 }
                 let rs = match q {
                     RecResult::Potential => RuleState::Visited,
                     RecResult::Found => RuleState::VisitedPath,
                     RecResult::DeadEnd => RuleState::DeadEnd,
                 };
-                rules.insert(r.clone(), rs);
+                rules.insert(instances.get(r).unwrap_or(r).clone(), rs);
                 let fs = match q {
                     RecResult::Potential => FactState::Visited,
                     RecResult::Found => FactState::VisitedPath,
@@ -167,15 +221,44 @@ impl ReverseReasoning {
             NodeInfo::FactToProve(f) => {
                 facts.insert(f.clone(), FactState::Visited);
             }
+            NodeInfo::Any(n, _) | NodeInfo::All(n, _) => {
+                for i in n {
+                    Self::rec_recoloring(i, facts, rules, instances, seen);
+                }
+            }
+            //Поиск внутри отрицания не раскрашиваем, только сам факт.
+            NodeInfo::Not(f, _, q) => match q {
+                RecResult::Potential => {
+                    facts.insert(f.clone(), FactState::Visited);
+                }
+                RecResult::Found => (),
+                RecResult::DeadEnd => {
+                    facts.insert(f.clone(), FactState::DeadEnd);
+                }
+            },
             NodeInfo::ProvenFact(_) => (),
+            NodeInfo::Shared(f, g) => {
+                if seen.insert(f.clone()) {
+                    Self::rec_recoloring(&g.read(), facts, rules, instances, seen);
+                }
+            }
             NodeInfo::DeadEnd(f) => {
                 facts.insert(f.clone(), FactState::DeadEnd);
             }
             NodeInfo::Empty => unreachable!(),
         }
     }
-    //At least one branch solved
     fn rec_iterate(&mut self, node: &mut Node) -> RecResult {
+        let expanding = matches!(node.node_info, NodeInfo::FactToProve(_));
+        let pending = node.pending();
+        let res = self.rec_iterate_node(node);
+        if expanding || (pending && res != RecResult::Potential) {
+            self.changed = true;
+        }
+        res
+    }
+    //At least one branch solved
+    fn rec_iterate_node(&mut self, node: &mut Node) -> RecResult {
         match &mut node.node_info {
             NodeInfo::Or(f, v, status) => {
                 if *status != RecResult::Potential {
@@ -223,65 +306,258 @@ impl ReverseReasoning {
                 }
                 *status
             }
+            NodeInfo::Any(v, status) => {
+                if *status != RecResult::Potential {
+                    return *status;
+                }
+                let mut count_deadend = 0;
+                let mut ind_found = None;
+                for (i, n) in v.iter_mut().enumerate() {
+                    match self.rec_iterate(n) {
+                        RecResult::Potential => {}
+                        RecResult::Found => {
+                            ind_found = Some(i);
+                            break;
+                        }
+                        RecResult::DeadEnd => count_deadend += 1,
+                    }
+                }
+                //Оставляем только найденный вариант, как в Or.
+                if let Some(found) = ind_found {
+                    let t = v.swap_remove(found);
+                    v.clear();
+                    v.push(t);
+                    *status = RecResult::Found;
+                } else if count_deadend == v.len() {
+                    *status = RecResult::DeadEnd;
+                }
+                *status
+            }
+            //В отличие от And, продолжаем доказывать остальные цели, даже если одна недостижима.
+            NodeInfo::All(v, status) => {
+                if *status != RecResult::Potential {
+                    return *status;
+                }
+                let results: Vec<RecResult> = v.iter_mut().map(|n| self.rec_iterate(n)).collect();
+                if results.iter().all(|x| *x == RecResult::Found) {
+                    *status = RecResult::Found;
+                } else if !results.contains(&RecResult::Potential) {
+                    *status = RecResult::DeadEnd;
+                }
+                *status
+            }
+            NodeInfo::Not(_, n, status) => {
+                if *status != RecResult::Potential {
+                    return *status;
+                }
+                *status = match self.rec_iterate(n) {
+                    RecResult::Potential => RecResult::Potential,
+                    RecResult::Found => RecResult::DeadEnd,
+                    RecResult::DeadEnd => RecResult::Found,
+                };
+                *status
+            }
+            //Цель проходится один раз за шаг, сколько бы ссылок на неё ни было.
+            NodeInfo::Shared(f, g) => {
+                if let Some(&res) = self.visited.get(f) {
+                    return res;
+                }
+                self.visited.insert(f.clone(), RecResult::Potential);
+                let g = g.clone();
+                let res = self.rec_iterate(&mut g.0.write().unwrap());
+                self.visited.insert(f.clone(), res);
+                res
+            }
             NodeInfo::FactToProve(f) => {
                 //println!("Starting facts: {:?}", self.starting_facts);
                 if self.starting_facts.contains(f) {
-                    *node = Node {
-                        available_rules: node.available_rules.clone(),
-                        node_info: NodeInfo::ProvenFact(f.clone()),
-                    };
-                    RecResult::Found
-                } else if let Some(v) = self.reversed_rules.get(f) {
-                    let t: Vec<_> = v
-                        .iter()
-                        .filter(|&x| node.available_rules.contains(x))
-                        .collect();
-                    if !t.is_empty() {
-                        let available_rules: Arc<HashSet<Rule>> = Arc::new(
-                            node.available_rules
-                                .iter()
-                                .filter(|x| !t.contains(x))
-                                .cloned()
-                                .collect(),
-                        );
-                        let aval = available_rules.clone();
-                        *node = Node {
-                            available_rules,
-                            node_info: NodeInfo::Or(
-                                f.clone(),
-                                t.iter()
-                                    .map(|x| Node {
-                                        available_rules: aval.clone(),
-                                        node_info: NodeInfo::And(
-                                            f.clone(),
-                                            (*x).clone(),
-                                            x.reqs
-                                                .iter()
-                                                .map(|q| Node {
-                                                    available_rules: aval.clone(),
-                                                    node_info: NodeInfo::FactToProve(q.clone()),
-                                                })
-                                                .collect(),
-                                            RecResult::Potential,
-                                        ),
-                                    })
-                                    .collect(),
-                                RecResult::Potential,
-                            ),
-                        };
-                        RecResult::Potential
-                    } else {
-                        RecResult::DeadEnd
-                    }
-                } else {
-                    RecResult::DeadEnd
+                    node.node_info = NodeInfo::ProvenFact(f.clone());
+                    return RecResult::Found;
+                }
+                let f = f.clone();
+                //Уже созданная цель переиспользуется, даже если её поиск ещё идёт.
+                if let Some(g) = self.table.get(&f) {
+                    node.node_info = NodeInfo::Shared(f, g.clone());
+                    return self.rec_iterate(node);
                 }
+                let t = self.candidates(&f);
+                let goal = if t.is_empty() {
+                    NodeInfo::DeadEnd(f.clone())
+                } else {
+                    NodeInfo::Or(
+                        f.clone(),
+                        t.iter()
+                            .map(|x| Node {
+                                node_info: NodeInfo::And(
+                                    f.clone(),
+                                    x.clone(),
+                                    self.requirements(x),
+                                    RecResult::Potential,
+                                ),
+                            })
+                            .collect(),
+                        RecResult::Potential,
+                    )
+                };
+                let goal = Node { node_info: goal };
+                let res = goal.status();
+                let g = Goal(Arc::new(RwLock::new(goal)));
+                self.table.insert(f.clone(), g.clone());
+                self.visited.insert(f.clone(), res);
+                node.node_info = NodeInfo::Shared(f, g);
+                res
             }
             NodeInfo::ProvenFact(_) => RecResult::Found,
             NodeInfo::DeadEnd(_) => RecResult::DeadEnd,
             NodeInfo::Empty => unreachable!(),
         }
     }
+    //Правила, которыми можно получить f: обычные и частные случаи правил с переменными.
+    //Циклы через уже использованные правила ловит таблица целей.
+    fn candidates(&mut self, f: &Fact) -> Vec<Rule> {
+        let mut t: Vec<Rule> = self.reversed_rules.get(f).cloned().unwrap_or_default();
+        for s in &self.schemas {
+            for b in unify::heads(s, f) {
+                for b in unify::ground(s, b, &self.constants) {
+                    let i = unify::instantiate(s, &b);
+                    if !t.contains(&i) {
+                        self.instances.insert(i.clone(), s.clone());
+                        t.push(i);
+                    }
+                }
+            }
+        }
+        t
+    }
+    //Узлы для всего, что нужно правилу: фактов, групп, катализаторов и отрицаний.
+    fn requirements(&self, rule: &Rule) -> Vec<Node> {
+        rule.reqs
+            .iter()
+            .map(|q| Node::prove(&q.fact))
+            .chain(rule.groups.iter().map(|g| Node {
+                node_info: NodeInfo::Any(
+                    g.iter().map(|q| Node::prove(&q.fact)).collect(),
+                    RecResult::Potential,
+                ),
+            }))
+            .chain(rule.catalysts.iter().map(Node::prove))
+            .chain(rule.negs.iter().map(|q| Self::negation(q.clone())))
+            .collect()
+    }
+    fn negation(fact: Fact) -> Node {
+        let proof = Node::prove(&fact);
+        Node {
+            node_info: NodeInfo::Not(fact, Box::new(proof), RecResult::Potential),
+        }
+    }
+    //Дерево вывода первой цели, если поиск его нашёл.
+    pub fn proof(&self) -> Option<RuleTree> {
+        match Self::proof_rec(self.target_nodes().first()?.1, &mut HashMap::new())? {
+            Source::Rule(t) => Some((*t).clone()),
+            Source::BasicFact(_) => None,
+        }
+    }
+    //После успеха в Or и Any остаётся только найденная ветка. Отрицания источников не дают.
+    //Поддерево общей цели строится один раз и делится через Arc.
+    fn proof_rec(node: &Node, memo: &mut HashMap<Fact, Option<Source>>) -> Option<Source> {
+        match &node.node_info {
+            NodeInfo::Or(_, v, RecResult::Found) | NodeInfo::Any(v, RecResult::Found) => {
+                Self::proof_rec(v.first()?, memo)
+            }
+            NodeInfo::Shared(f, g) => {
+                if let Some(s) = memo.get(f) {
+                    return s.clone();
+                }
+                let s = Self::proof_rec(&g.read(), memo);
+                memo.insert(f.clone(), s.clone());
+                s
+            }
+            NodeInfo::And(f, r, v, RecResult::Found) => Some(Source::Rule(Arc::new(RuleTree {
+                fact: f.clone(),
+                rule: Some(r.clone()),
+                sources: v.iter().filter_map(|x| Self::proof_rec(x, memo)).collect(),
+            }))),
+            NodeInfo::ProvenFact(f) => Some(Source::BasicFact(f.clone())),
+            _ => None,
+        }
+    }
+    fn model(&self) -> &HashSet<Fact> {
+        self.model.get_or_init(|| {
+            let e = Engine::new(
+                self.all_facts.clone(),
+                self.starting_facts.clone(),
+                self.all_rules.clone(),
+            );
+            Arc::new(e.model())
+        })
+    }
+    //Почему цели не выводятся: для каждого правила, дающего факт, - чего ему не хватает,
+    //и так до недостающих начальных фактов. Выводимые цели пропускаются.
+    pub fn explain(&self) -> Vec<WhyNot> {
+        let mut seen = HashSet::new();
+        self.targets
+            .iter()
+            .filter(|x| !self.model().contains(*x))
+            .map(|x| self.why_not(x, &mut vec![], &mut seen))
+            .collect()
+    }
+    //path - факты, которые сейчас объясняются; seen - уже объяснённые, чтобы не повторяться.
+    fn why_not(&self, fact: &Fact, path: &mut Vec<Fact>, seen: &mut HashSet<Fact>) -> WhyNot {
+        let reason = if path.contains(fact) {
+            Reason::Cycle
+        } else if !seen.insert(fact.clone()) {
+            Reason::Repeated
+        } else {
+            let mut rules = self.producers(fact);
+            if rules.is_empty() {
+                Reason::Missing
+            } else {
+                path.push(fact.clone());
+                let mut failed = vec![];
+                for r in rules.drain(..) {
+                    let mut unmet = vec![];
+                    for x in r.reqs.iter().map(|x| &x.fact).chain(&r.catalysts) {
+                        if !self.model().contains(x) {
+                            unmet.push(Unmet::Fact(self.why_not(x, path, seen)));
+                        }
+                    }
+                    for g in &r.groups {
+                        if !g.iter().any(|x| self.model().contains(&x.fact)) {
+                            let options = g.iter().map(|x| self.why_not(&x.fact, path, seen));
+                            unmet.push(Unmet::Group(options.collect()));
+                        }
+                    }
+                    for x in &r.negs {
+                        if self.model().contains(x) {
+                            unmet.push(Unmet::Present(x.clone()));
+                        }
+                    }
+                    failed.push((r, unmet));
+                }
+                path.pop();
+                Reason::Rules(failed)
+            }
+        };
+        WhyNot {
+            fact: fact.clone(),
+            reason,
+        }
+    }
+    //Все правила и частные случаи правил с переменными, дающие fact.
+    fn producers(&self, fact: &Fact) -> Vec<Rule> {
+        let mut res: Vec<Rule> = self.reversed_rules.get(fact).cloned().unwrap_or_default();
+        for s in &self.schemas {
+            for b in unify::heads(s, fact) {
+                for b in unify::ground(s, b, &self.constants) {
+                    let i = unify::instantiate(s, &b);
+                    if !res.contains(&i) {
+                        res.push(i);
+                    }
+                }
+            }
+        }
+        res
+    }
     pub fn get_applied_rules(&self) -> impl Iterator<Item = Rule> {
         let t = self.get_applied_rules_unfiltered();
         let mut v = Vec::with_capacity(t.len());
@@ -295,38 +571,25 @@ impl ReverseReasoning {
         v.into_iter()
     }
     fn get_applied_rules_unfiltered(&self) -> Vec<Rule> {
-        match &self.root.node_info {
-            NodeInfo::Or(f, _, s) => {
-                if *s == RecResult::Found && f == &self.target_fact {
-                    self.get_applied_rules_rec(&self.root, true)
-                } else {
-                    self.get_applied_rules_rec(&self.root, false)
-                }
-            }
-            NodeInfo::And(f, _, _, s) => {
-                if *s == RecResult::Found && f == &self.target_fact {
-                    self.get_applied_rules_rec(&self.root, true)
-                } else {
-                    self.get_applied_rules_rec(&self.root, false)
-                }
-            }
-            NodeInfo::FactToProve(_) => self.get_applied_rules_rec(&self.root, false),
-            NodeInfo::ProvenFact(f) => self.get_applied_rules_rec(&self.root, false),
-            NodeInfo::DeadEnd(_) => self.get_applied_rules_rec(&self.root, false),
-            NodeInfo::Empty => self.get_applied_rules_rec(&self.root, false),
-        }
+        let is_final = self.root.status() == RecResult::Found;
+        self.get_applied_rules_rec(&self.root, is_final, &mut HashSet::new())
     }
-    fn get_applied_rules_rec(&self, node: &Node, is_final: bool) -> Vec<Rule> {
+    fn get_applied_rules_rec(
+        &self,
+        node: &Node,
+        is_final: bool,
+        seen: &mut HashSet<Fact>,
+    ) -> Vec<Rule> {
         let mut t = vec![];
         match &node.node_info {
             NodeInfo::Or(_, r, q) => {
                 for i in r {
-                    t.append(&mut self.get_applied_rules_rec(i, is_final));
+                    t.append(&mut self.get_applied_rules_rec(i, is_final, seen));
                 }
             }
             NodeInfo::And(f, r, n, q) => {
                 for i in n {
-                    t.append(&mut self.get_applied_rules_rec(i, is_final));
+                    t.append(&mut self.get_applied_rules_rec(i, is_final, seen));
                 }
                 let rs = match q {
                     RecResult::Potential => !is_final,
@@ -338,6 +601,17 @@ impl ReverseReasoning {
                 }
             }
             NodeInfo::FactToProve(f) => {}
+            NodeInfo::Any(n, _) | NodeInfo::All(n, _) => {
+                for i in n {
+                    t.append(&mut self.get_applied_rules_rec(i, is_final, seen));
+                }
+            }
+            NodeInfo::Not(..) => (),
+            NodeInfo::Shared(f, g) => {
+                if seen.insert(f.clone()) {
+                    t.append(&mut self.get_applied_rules_rec(&g.read(), is_final, seen))
+                }
+            }
             NodeInfo::ProvenFact(_) => (),
             NodeInfo::DeadEnd(f) => {}
             NodeInfo::Empty => unreachable!(),
@@ -353,16 +627,348 @@ pub enum RecResult {
 }
 #[derive(Debug, Clone)]
 pub struct Node {
-    pub available_rules: Arc<HashSet<Rule>>,
     pub node_info: NodeInfo,
 }
 
+impl Node {
+    fn prove(fact: &Fact) -> Node {
+        Node {
+            node_info: NodeInfo::FactToProve(fact.clone()),
+        }
+    }
+    pub fn status(&self) -> RecResult {
+        match &self.node_info {
+            NodeInfo::Or(_, _, s)
+            | NodeInfo::And(_, _, _, s)
+            | NodeInfo::Any(_, s)
+            | NodeInfo::All(_, s)
+            | NodeInfo::Not(_, _, s) => *s,
+            NodeInfo::Shared(_, g) => g.read().status(),
+            NodeInfo::ProvenFact(_) => RecResult::Found,
+            NodeInfo::DeadEnd(_) => RecResult::DeadEnd,
+            NodeInfo::FactToProve(_) | NodeInfo::Empty => RecResult::Potential,
+        }
+    }
+    //Узел ещё не закончен и не ссылка на цель (у цели свой узел).
+    fn pending(&self) -> bool {
+        !matches!(self.node_info, NodeInfo::Shared(..)) && self.status() == RecResult::Potential
+    }
+    //Ждёт ли незаконченная часть поддерева отрицания. seen - уже пройденные цели.
+    fn waits_for_negation(&self, seen: &mut HashSet<Fact>) -> bool {
+        match &self.node_info {
+            NodeInfo::Not(_, _, s) => *s == RecResult::Potential,
+            NodeInfo::Or(_, v, RecResult::Potential)
+            | NodeInfo::And(_, _, v, RecResult::Potential)
+            | NodeInfo::Any(v, RecResult::Potential)
+            | NodeInfo::All(v, RecResult::Potential) => {
+                v.iter().any(|x| x.waits_for_negation(seen))
+            }
+            NodeInfo::Shared(f, g) => seen.insert(f.clone()) && g.read().waits_for_negation(seen),
+            _ => false,
+        }
+    }
+}
+//Узел цели из таблицы поиска, общий для всех мест, где нужен факт.
+#[derive(Clone)]
+pub struct Goal(Arc<RwLock<Node>>);
+impl Goal {
+    pub fn read(&self) -> std::sync::RwLockReadGuard<'_, Node> {
+        self.0.read().unwrap()
+    }
+}
+//Через цели дерево может замкнуться в цикл, так что внутрь не заходим.
+impl fmt::Debug for Goal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.try_read() {
+            Ok(n) => write!(f, "Goal({:?})", n.status()),
+            Err(_) => write!(f, "Goal(..)"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum NodeInfo {
     Or(Fact, Vec<Node>, RecResult),
     And(Fact, Rule, Vec<Node>, RecResult),
     FactToProve(Fact),
+    //Группа {a | b}: достаточно доказать один из вариантов.
+    Any(Vec<Node>, RecResult),
+    //Корень для нескольких целей: нужно доказать все.
+    All(Vec<Node>, RecResult),
+    //Факт не должен доказываться: найденное доказательство - тупик.
+    Not(Fact, Box<Node>, RecResult),
     ProvenFact(Fact),
+    //Ссылка на узел цели: один на факт, пока ищется и после.
+    Shared(Fact, Goal),
     DeadEnd(Fact),
     Empty,
 }
+
+//Объяснение, почему факт не выводится.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhyNot {
+    pub fact: Fact,
+    pub reason: Reason,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    //Не начальный факт, и ни одно правило его не даёт.
+    Missing,
+    //Каждое правило, дающее факт, с тем, чего ему не хватает.
+    Rules(Vec<(Rule, Vec<Unmet>)>),
+    //Получается только через самого себя.
+    Cycle,
+    //Уже объяснён выше.
+    Repeated,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unmet {
+    Fact(WhyNot),
+    //Ни один вариант группы не выводится.
+    Group(Vec<WhyNot>),
+    //Факт под отрицанием выводится.
+    Present(Fact),
+}
+impl WhyNot {
+    //Недостающие начальные факты, к которым сводится объяснение.
+    pub fn missing_facts(&self) -> Vec<Fact> {
+        let mut res = vec![];
+        if let Reason::Rules(rules) = &self.reason {
+            for (_, unmet) in rules {
+                for u in unmet {
+                    match u {
+                        Unmet::Fact(w) => res.extend(w.missing_facts()),
+                        Unmet::Group(v) => v.iter().for_each(|w| res.extend(w.missing_facts())),
+                        Unmet::Present(_) => (),
+                    }
+                }
+            }
+        } else if self.reason == Reason::Missing {
+            res.push(self.fact.clone());
+        }
+        res
+    }
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let pad = depth * 2;
+        match &self.reason {
+            Reason::Missing => writeln!(
+                f,
+                "{:pad$}{}: not a starting fact and no rule produces it",
+                "", self.fact
+            ),
+            Reason::Cycle => writeln!(f, "{:pad$}{}: only derivable from itself", "", self.fact),
+            Reason::Repeated => writeln!(f, "{:pad$}{}: see above", "", self.fact),
+            Reason::Rules(rules) => {
+                writeln!(f, "{:pad$}{}: every rule producing it fails", "", self.fact)?;
+                for (r, unmet) in rules {
+                    writeln!(f, "{:w$}{}", "", r, w = pad + 2)?;
+                    for u in unmet {
+                        match u {
+                            Unmet::Fact(w) => w.write(f, depth + 2)?,
+                            Unmet::Group(v) => {
+                                writeln!(f, "{:w$}none of:", "", w = pad + 4)?;
+                                for w in v {
+                                    w.write(f, depth + 3)?;
+                                }
+                            }
+                            Unmet::Present(x) => {
+                                writeln!(f, "{:w$}!{}: {} is derivable", "", x, x, w = pad + 4)?
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl fmt::Display for WhyNot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn fact(e: &Engine, name: &str) -> Fact {
+        let f = e.all_possible_facts.iter().find(|x| x.to_string() == name);
+        f.unwrap().clone()
+    }
+
+    fn search(src: &str, target: &str) -> (ReverseReasoning, RevStepResult) {
+        let e = Engine::from_string(src).unwrap();
+        let mut r = ReverseReasoning::new(&e, fact(&e, target));
+        let res = r.build_tree(&NodeColoring::default());
+        (r, res)
+    }
+
+    #[test]
+    fn rule_is_indexed_under_each_output() {
+        let mut e = Engine::from_string("{ore} -> iron_ingot, slag\n{slag} -> gravel\n").unwrap();
+        e.starting_facts.insert(fact(&e, "ore"));
+        let mut r = ReverseReasoning::new(&e, fact(&e, "gravel"));
+        let ingot = &r.reversed_rules[&fact(&e, "iron_ingot")];
+        assert_eq!(ingot, &r.reversed_rules[&fact(&e, "slag")]);
+        assert_eq!(r.build_tree(&NodeColoring::default()), RevStepResult::Found);
+    }
+
+    #[test]
+    fn negation_as_failure() {
+        let src = "{water, !frozen} -> liquid_water\n{cold} -> frozen\n";
+        let (_, res) = search(&format!("{}start: water\n", src), "liquid_water");
+        assert_eq!(res, RevStepResult::Found);
+        let (_, res) = search(&format!("{}start: water, cold\n", src), "liquid_water");
+        assert_eq!(res, RevStepResult::NotProved);
+    }
+
+    #[test]
+    fn any_group_member_is_enough() {
+        let src = "{log} -> charcoal\n{coal | charcoal, stick} -> torch\n";
+        let (r, res) = search(&format!("{}start: log, stick\n", src), "torch");
+        assert_eq!(res, RevStepResult::Found);
+        assert_eq!(r.get_applied_rules().count(), 2);
+        let (_, res) = search(&format!("{}start: stick\n", src), "torch");
+        assert_eq!(res, RevStepResult::NotProved);
+    }
+
+    #[test]
+    fn substitutions_reach_subgoals() {
+        let src = "{parent(X, Y)} -> anc(X, Y)\n{parent(X, Y), anc(Y, Z)} -> anc(X, Z)\n\
+                   start: parent(a, b), parent(b, c), parent(c, d)\n";
+        let e = Engine::from_string(src).unwrap();
+        let prove = |goal: &str| {
+            let goal = Arc::new(parser::parse_fact(goal).unwrap());
+            ReverseReasoning::new(&e, goal).build_tree(&NodeColoring::default())
+        };
+        assert_eq!(prove("anc(a, d)"), RevStepResult::Found);
+        assert_eq!(prove("anc(d, a)"), RevStepResult::NotProved);
+    }
+
+    #[test]
+    fn tag_is_expanded_into_members() {
+        let src = "tag planks = oak, birch\n{log} -> birch\n{#planks} -> stick\nstart: log\n";
+        let (r, res) = search(src, "stick");
+        assert_eq!(res, RevStepResult::Found);
+        assert_eq!(r.get_applied_rules().count(), 2);
+    }
+
+    const PICKAXE: &str = "{plank, iron} -> pickaxe\n{wood, !wet} -> pickaxe\n{wood} -> plank\n\
+                           {wood} -> wet\n{ore} -> iron\n{coal | charcoal} -> iron\nstart: wood\n";
+
+    #[test]
+    fn explain_lists_unmet_requirements() {
+        let (r, res) = search(PICKAXE, "pickaxe");
+        assert_eq!(res, RevStepResult::NotProved);
+        let why = &r.explain()[0];
+        let text = why.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "pickaxe: every rule producing it fails",
+                "  {plank, iron} -> pickaxe",
+                "    iron: every rule producing it fails",
+                "      {ore} -> iron",
+                "        ore: not a starting fact and no rule produces it",
+                "      {coal | charcoal} -> iron",
+                "        none of:",
+                "          coal: not a starting fact and no rule produces it",
+                "          charcoal: not a starting fact and no rule produces it",
+                "  {wood, !wet} -> pickaxe",
+                "    !wet: wet is derivable",
+            ]
+        );
+        let missing: Vec<String> = why.missing_facts().iter().map(|x| x.to_string()).collect();
+        assert_eq!(missing, ["ore", "coal", "charcoal"]);
+        let (r, _) = search(PICKAXE, "plank");
+        assert!(r.explain().is_empty());
+    }
+
+    #[test]
+    fn all_targets_must_be_proved() {
+        let e = Engine::from_string("z\n{a} -> b\n{b} -> c\nstart: a\n").unwrap();
+        let mut r = ReverseReasoning::with_targets(&e, vec![fact(&e, "c"), fact(&e, "b")]);
+        assert_eq!(r.build_tree(&NodeColoring::default()), RevStepResult::Found);
+        let coloring = NodeColoring::default();
+        let mut r = ReverseReasoning::with_targets(&e, vec![fact(&e, "c"), fact(&e, "z")]);
+        assert_eq!(r.build_tree(&coloring), RevStepResult::NotProved);
+        let facts = coloring.facts.read().unwrap();
+        assert_eq!(facts[&fact(&e, "c")], FactState::TargetVisited);
+        assert_eq!(facts[&fact(&e, "z")], FactState::TargetNotPossible);
+        let why = r.explain();
+        assert_eq!(why.len(), 1);
+        assert_eq!(why[0].fact, fact(&e, "z"));
+    }
+
+    #[test]
+    fn shared_subgoal_is_proved_once() {
+        let src = "{wood} -> stick\n{stick, iron} -> pick\n{stick, stone} -> axe\n\
+                   {stick, pick, axe} -> tool\nstart: wood, iron, stone\n";
+        let (r, res) = search(src, "tool");
+        assert_eq!(res, RevStepResult::Found);
+        assert_eq!(r.table.len(), 4);
+        let rules: Vec<String> = r.get_applied_rules().map(|x| x.to_string()).collect();
+        assert_eq!(rules.iter().filter(|x| *x == "{wood} -> stick").count(), 1);
+        assert_eq!(r.proof().unwrap().rules().len(), 6);
+    }
+
+    #[test]
+    fn cycles_end() {
+        let cycle = "{a} -> b\n{b} -> a\n{b} -> d\n";
+        let (_, res) = search(&format!("{}{{c}} -> a\nstart: c\n", cycle), "d");
+        assert_eq!(res, RevStepResult::Found);
+        let (_, res) = search(&format!("{}start: c\n", cycle), "d");
+        assert_eq!(res, RevStepResult::NotProved);
+    }
+
+    #[test]
+    fn negation_of_cycle() {
+        let src = "{y} -> z\n{z} -> y\n{!y, c} -> x\nstart: c\n";
+        let (_, res) = search(src, "x");
+        assert_eq!(res, RevStepResult::Found);
+        let (_, res) = search(&format!("{}{{c}} -> z\n", src), "x");
+        assert_eq!(res, RevStepResult::NotProved);
+    }
+
+    //Узлы дерева, узел каждой цели считается один раз.
+    fn size(node: &Node, seen: &mut HashSet<Fact>) -> usize {
+        1 + match &node.node_info {
+            NodeInfo::Or(_, v, _)
+            | NodeInfo::And(_, _, v, _)
+            | NodeInfo::Any(v, _)
+            | NodeInfo::All(v, _) => v.iter().map(|x| size(x, seen)).sum(),
+            NodeInfo::Not(_, n, _) => size(n, seen),
+            NodeInfo::Shared(f, g) if seen.insert(f.clone()) => size(&g.read(), seen),
+            _ => 0,
+        }
+    }
+    //{f(i), f(i-1)} -> f(i+1): без общих целей дерево растёт как числа Фибоначчи.
+    fn fibonacci(n: usize) -> (ReverseReasoning, RevStepResult) {
+        let mut src = String::new();
+        for i in 1..n {
+            src += &format!("{{f{}, f{}}} -> f{}\n", i, i - 1, i + 1);
+        }
+        src += "start: f0, f1\n";
+        search(&src, &format!("f{}", n))
+    }
+
+    #[test]
+    fn shared_subgoals_grow_linearly() {
+        let (small, res) = fibonacci(50);
+        assert_eq!(res, RevStepResult::Found);
+        let (large, res) = fibonacci(200);
+        assert_eq!(res, RevStepResult::Found);
+        assert_eq!(large.table.len(), 199);
+        let small = size(&small.root, &mut HashSet::new());
+        let large = size(&large.root, &mut HashSet::new());
+        assert!(
+            large <= 4 * small + 10,
+            "{} nodes for 4x the rules of {}",
+            large,
+            small
+        );
+    }
+}
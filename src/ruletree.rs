@@ -1,11 +1,15 @@
+use core::fmt;
 use std::sync::Arc;
 
 use crate::fact::{Fact, Rule};
+//Вывод факта fact правилом rule; sources - откуда взялось каждое требование правила.
+//Без правила - лист: fact начальный, выводить его не нужно.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 
 pub struct RuleTree {
-    sources: Vec<Source>,
-    rule: Rule,
+    pub fact: Fact,
+    pub rule: Option<Rule>,
+    pub sources: Vec<Source>,
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 
@@ -13,3 +17,53 @@ pub enum Source {
     Rule(Arc<RuleTree>),
     BasicFact(Fact),
 }
+impl Source {
+    pub fn fact(&self) -> &Fact {
+        match self {
+            Source::Rule(t) => &t.fact,
+            Source::BasicFact(f) => f,
+        }
+    }
+}
+impl RuleTree {
+    //Правила в порядке применения: сначала поддеревья, потом само правило.
+    pub fn rules(&self) -> Vec<Rule> {
+        let mut res = vec![];
+        for s in &self.sources {
+            if let Source::Rule(t) = s {
+                res.extend(t.rules());
+            }
+        }
+        res.extend(self.rule.clone());
+        res
+    }
+    //Начальные факты, на которые опирается вывод.
+    pub fn basic_facts(&self) -> Vec<Fact> {
+        let mut res = vec![];
+        for s in &self.sources {
+            match s {
+                Source::Rule(t) => res.extend(t.basic_facts()),
+                Source::BasicFact(f) => res.push(f.clone()),
+            }
+        }
+        res
+    }
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        match &self.rule {
+            Some(r) => writeln!(f, "{:w$}{} <- {}", "", self.fact, r, w = depth * 2)?,
+            None => writeln!(f, "{:w$}{}", "", self.fact, w = depth * 2)?,
+        }
+        for s in &self.sources {
+            match s {
+                Source::Rule(t) => t.write(f, depth + 1)?,
+                Source::BasicFact(x) => writeln!(f, "{:w$}{}", "", x, w = (depth + 1) * 2)?,
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for RuleTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::fact::{CoreFact, CoreRule, Fact, Group, Rule, Stack};
+
+//Подстановка: имя переменной -> терм.
+pub type Bindings = HashMap<String, CoreFact>;
+
+fn resolve<'a>(mut f: &'a CoreFact, b: &'a Bindings) -> &'a CoreFact {
+    while let CoreFact::Var(v) = f {
+        match b.get(v) {
+            Some(x) => f = x,
+            None => break,
+        }
+    }
+    f
+}
+fn occurs(var: &str, f: &CoreFact, b: &Bindings) -> bool {
+    match resolve(f, b) {
+        CoreFact::Var(v) => v == var,
+        CoreFact::Symbol(_) => false,
+        CoreFact::Symbols(v) => v.iter().any(|x| occurs(var, x, b)),
+    }
+}
+//Дополняет bindings так, чтобы a и c совпали. При неудаче bindings может остаться изменённым.
+pub fn unify(a: &CoreFact, c: &CoreFact, b: &mut Bindings) -> bool {
+    let a = resolve(a, b).clone();
+    let c = resolve(c, b).clone();
+    match (&a, &c) {
+        (CoreFact::Var(x), CoreFact::Var(y)) if x == y => true,
+        (CoreFact::Var(x), t) | (t, CoreFact::Var(x)) => {
+            if occurs(x, t, b) {
+                return false;
+            }
+            b.insert(x.clone(), t.clone());
+            true
+        }
+        (CoreFact::Symbol(x), CoreFact::Symbol(y)) => x == y,
+        (CoreFact::Symbols(x), CoreFact::Symbols(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| unify(x, y, b))
+        }
+        _ => false,
+    }
+}
+pub fn substitute(f: &CoreFact, b: &Bindings) -> CoreFact {
+    match resolve(f, b) {
+        CoreFact::Symbols(v) => CoreFact::Symbols(v.iter().map(|x| substitute(x, b)).collect()),
+        f => f.clone(),
+    }
+}
+fn vars(f: &CoreFact, res: &mut Vec<String>) {
+    match f {
+        CoreFact::Var(v) => {
+            if !res.contains(v) {
+                res.push(v.clone());
+            }
+        }
+        CoreFact::Symbol(_) => (),
+        CoreFact::Symbols(v) => v.iter().for_each(|x| vars(x, res)),
+    }
+}
+pub fn rule_vars(r: &CoreRule) -> Vec<String> {
+    let mut res = vec![];
+    for f in r
+        .reqs
+        .iter()
+        .chain(r.groups.iter().flatten())
+        .map(|x| &*x.fact)
+        .chain(r.catalysts.iter().chain(r.negs.iter()).map(|x| &**x))
+        .chain(r.outs.iter().map(|x| &*x.fact))
+    {
+        vars(f, &mut res);
+    }
+    res
+}
+//Переменные, которые встречаются в правиле, но не в обычных требованиях и катализаторах.
+pub fn unbound_vars(r: &CoreRule) -> Vec<String> {
+    let mut bound = vec![];
+    for x in r.reqs.iter().map(|x| &x.fact).chain(&r.catalysts) {
+        vars(x, &mut bound);
+    }
+    rule_vars(r)
+        .into_iter()
+        .filter(|x| !bound.contains(x))
+        .collect()
+}
+
+//Частный случай правила при подстановке b.
+pub fn instantiate(r: &CoreRule, b: &Bindings) -> Rule {
+    let fact = |f: &Fact| Arc::new(substitute(f, b));
+    let stack = |s: &Stack| Stack::new(fact(&s.fact), s.count);
+    Arc::new(CoreRule {
+        name: r.name.clone(),
+        reqs: r.reqs.iter().map(stack).collect(),
+        groups: r
+            .groups
+            .iter()
+            .map(|g| Group {
+                tag: g.tag.clone(),
+                options: g.iter().map(stack).collect(),
+            })
+            .collect(),
+        catalysts: r.catalysts.iter().map(fact).collect(),
+        negs: r.negs.iter().map(fact).collect(),
+        outs: r.outs.iter().map(stack).collect(),
+        attrs: r.attrs.clone(),
+    })
+}
+
+//Все подстановки, при которых обычные требования и катализаторы правила есть среди facts.
+pub fn matches(r: &CoreRule, facts: &HashSet<Fact>) -> Vec<Bindings> {
+    let mut res = vec![Bindings::new()];
+    for req in r.reqs.iter().map(|x| &x.fact).chain(&r.catalysts) {
+        let mut next = vec![];
+        for b in &res {
+            let pattern = substitute(req, b);
+            if pattern.is_ground() {
+                if facts.contains(&pattern) {
+                    next.push(b.clone());
+                }
+                continue;
+            }
+            for f in facts {
+                let mut b = b.clone();
+                if unify(&pattern, f, &mut b) {
+                    next.push(b);
+                }
+            }
+        }
+        res = next;
+    }
+    res
+}
+
+//Подстановки, при которых правило получает goal.
+pub fn heads(r: &CoreRule, goal: &CoreFact) -> Vec<Bindings> {
+    r.outs
+        .iter()
+        .filter_map(|out| {
+            let mut b = Bindings::new();
+            unify(&out.fact, goal, &mut b).then_some(b)
+        })
+        .collect()
+}
+
+//Доводит подстановку до полной, перебирая для свободных переменных все известные константы.
+pub fn ground(r: &CoreRule, b: Bindings, constants: &[CoreFact]) -> Vec<Bindings> {
+    let mut res = vec![b];
+    for v in rule_vars(r) {
+        if res.first().is_some_and(|b| b.contains_key(&v)) {
+            continue;
+        }
+        res = res
+            .into_iter()
+            .flat_map(|b| {
+                let v = v.clone();
+                constants.iter().map(move |c| {
+                    let mut b = b.clone();
+                    b.insert(v.clone(), c.clone());
+                    b
+                })
+            })
+            .collect();
+    }
+    res
+}
+
+//Аргументы фактов без переменных: из них берутся значения для переменных.
+pub fn constants<'a>(facts: impl Iterator<Item = &'a Fact>) -> Vec<CoreFact> {
+    fn add(f: &CoreFact, res: &mut Vec<CoreFact>) {
+        if let CoreFact::Symbols(v) = f {
+            for x in v.iter().skip(1) {
+                if x.is_ground() && !res.contains(x) {
+                    res.push(x.clone());
+                }
+                add(x, res);
+            }
+        }
+    }
+    let mut res = vec![];
+    for f in facts {
+        add(f, &mut res);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Engine, parser};
+
+    fn term(s: &str) -> CoreFact {
+        parser::parse_fact(s).unwrap()
+    }
+
+    #[test]
+    fn unifies_nested_terms() {
+        let mut b = Bindings::new();
+        assert!(unify(
+            &term("anc(X, pair(Y, c))"),
+            &term("anc(a, pair(b, c))"),
+            &mut b
+        ));
+        assert_eq!(substitute(&term("p(Y, X)"), &b), term("p(b, a)"));
+        assert!(!unify(
+            &term("p(X, X)"),
+            &term("p(a, b)"),
+            &mut Bindings::new()
+        ));
+        assert!(!unify(
+            &term("p(X, X)"),
+            &term("p(Y, q(Y))"),
+            &mut Bindings::new()
+        ));
+    }
+
+    #[test]
+    fn matches_join_requirements() {
+        let e = Engine::from_string(
+            "{parent(X, Y), parent(Y, Z)} -> grand(X, Z)\n\
+             start: parent(a, b), parent(b, c), parent(c, d), parent(x, y)\n",
+        )
+        .unwrap();
+        let mut grand: Vec<String> = matches(&e.rules[0], &e.starting_facts)
+            .iter()
+            .map(|b| instantiate(&e.rules[0], b).outs[0].fact.to_string())
+            .collect();
+        grand.sort();
+        assert_eq!(grand, ["grand(a, c)", "grand(b, d)"]);
+    }
+}